@@ -0,0 +1,175 @@
+use std::ascii::AsciiExt;
+use std::path::PathBuf;
+use data::category_path;
+use constants::CATEGORY_SEPARATOR;
+
+const USAGE: &'static str = "Usage: pbook-gui [options]
+
+Options:
+    --dir <path>          download into <path> for this session
+    --threads <n>         number of download threads for this session
+    --theme <name>        theme for this session, \"auto\" follows the desktop
+    --list <file|url>     read the book list from a file or url instead of the built in one
+    --format <id>         how the list is written: fpb, awesome, csv, tsv or json
+    --enable <category>   queue every book in a category, can be given more than once
+    --start               start the enabled categories instead of leaving them paused
+    --subscribe <name>    queue new books of a category each time the list is read, repeatable
+    --refresh <minutes>   reread the list every <minutes>, 0 turns it off
+    --headless            download without the gui, exits once the queue is done unless
+                          the list is being refreshed
+    --lint                print the lines of the list the parser skipped and exit,
+                          1 if any of them can be fixed
+    --json                print the --lint report as json
+    --config <path>       read and save settings in <path>
+    -v, --verbose         log debug messages too
+    -q, --quiet           only print warnings and errors, the log file is kept as usual
+    --version             print the version and exit
+    --help                print this message and exit";
+
+// Settings given on the command line, they override the config without being saved
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    pub dir: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub theme: Option<String>,
+    pub list: Option<String>,
+    pub format: Option<String>,
+    pub enable: Vec<String>,
+    pub start: bool,
+    pub subscribe: Vec<String>,
+    pub refresh: Option<u64>,
+    pub headless: bool,
+    pub lint: bool,
+    pub json: bool,
+    pub config: Option<PathBuf>,
+    pub verbose: bool,
+    pub quiet: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+pub fn usage() -> &'static str {
+    USAGE
+}
+
+// Whether a category from the list is the one given to --enable. Either its whole name or
+// one of its headings works, so a parent takes its subcategories along.
+pub fn category_matches(category_name: &str, wanted: &str) -> bool {
+    let wanted = wanted.trim();
+    let parent_prefix = format!("{}{}", wanted, CATEGORY_SEPARATOR).to_ascii_lowercase();
+    category_name.trim().eq_ignore_ascii_case(wanted) ||
+    category_name.to_ascii_lowercase().starts_with(&parent_prefix) ||
+    category_path(category_name).iter().any(|title| title.trim().eq_ignore_ascii_case(wanted))
+}
+
+// args without the program name, both "--opt value" and "--opt=value" work
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.find('=') {
+            Some(idx) if arg.starts_with("--") => {
+                (arg[..idx].to_owned(), Some(arg[idx + 1..].to_owned()))
+            }
+            _ => (arg.clone(), None),
+        };
+        match &flag as &str {
+            "--start" => parsed.start = true,
+            "--headless" => parsed.headless = true,
+            "--lint" => parsed.lint = true,
+            "--json" => parsed.json = true,
+            "-v" | "--verbose" => parsed.verbose = true,
+            "-q" | "--quiet" => parsed.quiet = true,
+            "-h" | "--help" => parsed.help = true,
+            "-V" | "--version" => parsed.version = true,
+            "--dir" | "--threads" | "--theme" | "--list" | "--enable" | "--config" |
+            "--subscribe" | "--refresh" | "--format" => {
+                let value = match inline_value.or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => return Err(format!("{} needs a value", flag)),
+                };
+                match &flag as &str {
+                    "--dir" => parsed.dir = Some(PathBuf::from(value)),
+                    "--threads" => {
+                        parsed.threads = match value.parse::<usize>() {
+                            Ok(n) if n > 0 => Some(n),
+                            _ => return Err(format!("Invalid thread count \"{}\"", value)),
+                        }
+                    }
+                    "--theme" => parsed.theme = Some(value),
+                    "--list" => parsed.list = Some(value),
+                    "--format" => parsed.format = Some(value),
+                    "--enable" => parsed.enable.push(value),
+                    "--subscribe" => parsed.subscribe.push(value),
+                    "--refresh" => {
+                        parsed.refresh = match value.parse::<u64>() {
+                            Ok(minutes) => Some(minutes),
+                            _ => return Err(format!("Invalid refresh interval \"{}\"", value)),
+                        }
+                    }
+                    _ => parsed.config = Some(PathBuf::from(value)),
+                }
+            }
+            _ => return Err(format!("Unknown argument \"{}\"", arg)),
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::{parse_args, category_matches, Args};
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_flags_and_values() {
+        let args = parse(&["--dir", "books", "--threads=4", "-q", "--enable", "Rust",
+                           "--enable=Go"])
+                       .unwrap();
+        assert_eq!(args.dir, Some(PathBuf::from("books")));
+        assert_eq!(args.threads, Some(4));
+        assert!(args.quiet);
+        assert!(!args.verbose);
+        assert_eq!(args.enable, vec!["Rust".to_owned(), "Go".to_owned()]);
+    }
+
+    #[test]
+    fn refuses_unknown_flags() {
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["books"]).is_err());
+    }
+
+    #[test]
+    fn refuses_missing_values() {
+        assert_eq!(parse(&["--dir"]).unwrap_err(), "--dir needs a value");
+        assert!(parse(&["--list"]).is_err());
+    }
+
+    #[test]
+    fn refuses_bad_thread_counts() {
+        assert!(parse(&["--threads", "0"]).is_err());
+        assert!(parse(&["--threads", "-1"]).is_err());
+        assert!(parse(&["--threads=four"]).is_err());
+        assert!(parse(&["--refresh", "soon"]).is_err());
+    }
+
+    #[test]
+    fn matches_categories_ignoring_case() {
+        assert!(category_matches("Rust", "rust"));
+        assert!(category_matches("Rust", " RUST "));
+        assert!(!category_matches("Rust Embedded", "Rust"));
+    }
+
+    #[test]
+    fn matches_subcategories_by_parent_or_heading() {
+        let name = "Language Agnostic / Algorithms";
+        assert!(category_matches(name, "language agnostic"));
+        assert!(category_matches(name, "Algorithms"));
+        assert!(category_matches(name, "Language Agnostic / Algorithms"));
+        assert!(!category_matches(name, "Language"));
+    }
+}
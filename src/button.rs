@@ -9,3 +9,11 @@ pub fn is_right_click(ebutton: &EventButton) -> bool {
         false
     }
 }
+
+pub fn is_right_press(ebutton: &EventButton) -> bool {
+    if let EventType::ButtonPress = ebutton.get_event_type() {
+        ebutton.get_button() == 3
+    } else {
+        false
+    }
+}
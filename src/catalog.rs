@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use std::fs::{File, create_dir_all};
+use std::path::{Path, PathBuf};
+use rustc_serialize::json;
+use data::{Category, Download};
+use helper::{normalize_url, write_replacing};
+use pathtemplate::PathTemplate;
+use paths;
+use constants::CATALOG_FILE_NAME;
+
+// a book as the list had it when the snapshot was taken
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct CatalogEntry {
+    pub id: u64,
+    pub title: String,
+    pub url: String,
+    pub category: String,
+    // where it's saved under the download dir, None in snapshots from before this was kept
+    pub path: Option<String>,
+}
+
+// the id a book had in an older list and the one it has now
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Migration {
+    pub from: u64,
+    pub to: u64,
+}
+
+// The list and the unfinished downloads as of the last run, kept in the data dir
+#[derive(Debug, Clone, Default, RustcEncodable, RustcDecodable)]
+pub struct Snapshot {
+    pub entries: Vec<CatalogEntry>,
+    // every rename seen so far, so ids saved several list versions ago still resolve
+    pub migrations: Vec<Migration>,
+    pub selected: Vec<u64>,
+    // categories with a checked book, new books in them can be queued in one go
+    pub enabled_categories: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Moved,
+    UrlChanged,
+    Renamed,
+}
+
+impl ChangeKind {
+    pub fn label(&self) -> &'static str {
+        match *self {
+            ChangeKind::Added => "New",
+            ChangeKind::Removed => "Removed",
+            ChangeKind::Moved => "Moved",
+            ChangeKind::UrlChanged => "URL changed",
+            ChangeKind::Renamed => "Renamed",
+        }
+    }
+}
+
+// one difference between the snapshot and the current list
+#[derive(Debug, Clone)]
+pub struct CatalogChange {
+    pub kind: ChangeKind,
+    // the current id, the old one for a removed book
+    pub id: u64,
+    pub title: String,
+    pub url: String,
+    pub category: String,
+    // what it was before, the old category, url or title
+    pub previous: String,
+    // where a removed book was saved, if the snapshot knows
+    pub path: Option<String>,
+}
+
+// Maps ids saved by an earlier run onto the books of the current list
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    ids: HashSet<u64>,
+    migrations: HashMap<u64, u64>,
+    previous: Option<Snapshot>,
+}
+
+impl Catalog {
+    pub fn new(data: &[Category], previous: Option<Snapshot>) -> Catalog {
+        let ids = data.iter()
+                      .flat_map(|category| category.downloads().iter().map(|d| d.id()))
+                      .collect::<HashSet<u64>>();
+        let migrations = match previous {
+            Some(ref previous) => migrate(previous, data, &ids),
+            None => HashMap::new(),
+        };
+        if !migrations.is_empty() {
+            info!("{} books changed their id since the last run", migrations.len());
+        }
+        Catalog {
+            ids: ids,
+            migrations: migrations,
+            previous: previous,
+        }
+    }
+
+    // the current id of a book saved under an old one, None if it left the list
+    pub fn resolve(&self, id: u64) -> Option<u64> {
+        if self.ids.contains(&id) {
+            Some(id)
+        } else {
+            self.migrations.get(&id).cloned()
+        }
+    }
+
+    // categories the user had a book checked in last time
+    pub fn previous_categories(&self) -> Vec<String> {
+        match self.previous {
+            Some(ref previous) => previous.enabled_categories.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    // unfinished downloads from the last run, with their current ids
+    pub fn previous_selection(&self) -> Vec<u64> {
+        match self.previous {
+            Some(ref previous) => {
+                previous.selected.iter().filter_map(|&id| self.resolve(id)).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // what changed since the snapshot, by category, empty on the first run
+    pub fn changes(&self, data: &[Category]) -> Vec<CatalogChange> {
+        let previous = match self.previous {
+            Some(ref previous) => previous,
+            None => return Vec::new(),
+        };
+        let mut old_entries = previous.entries
+                                      .iter()
+                                      .map(|entry| (entry.id, entry))
+                                      .collect::<HashMap<u64, &CatalogEntry>>();
+        for (&from, &to) in self.migrations.iter() {
+            if let Some(entry) = old_entries.remove(&from) {
+                old_entries.insert(to, entry);
+            }
+        }
+        let mut changes = Vec::new();
+        for category in data.iter() {
+            for download in category.downloads() {
+                let change = |kind, previous: &str| {
+                    CatalogChange {
+                        kind: kind,
+                        id: download.id(),
+                        title: download.name().to_owned(),
+                        url: download.url().to_owned(),
+                        category: category.name().to_owned(),
+                        previous: previous.to_owned(),
+                        path: None,
+                    }
+                };
+                match old_entries.remove(&download.id()) {
+                    None => changes.push(change(ChangeKind::Added, "")),
+                    Some(entry) => {
+                        if entry.category != category.name() {
+                            changes.push(change(ChangeKind::Moved, &entry.category));
+                        }
+                        if normalize_url(&entry.url) != normalize_url(download.url()) {
+                            changes.push(change(ChangeKind::UrlChanged, &entry.url));
+                        }
+                        if entry.title != download.name() {
+                            changes.push(change(ChangeKind::Renamed, &entry.title));
+                        }
+                    }
+                }
+            }
+        }
+        // whatever wasn't matched left the list
+        for entry in old_entries.values() {
+            changes.push(CatalogChange {
+                kind: ChangeKind::Removed,
+                id: entry.id,
+                title: entry.title.clone(),
+                url: entry.url.clone(),
+                category: entry.category.clone(),
+                previous: String::new(),
+                path: entry.path.clone(),
+            });
+        }
+        changes.sort_by(|a, b| {
+            (&a.category, a.kind, &a.title).cmp(&(&b.category, b.kind, &b.title))
+        });
+        changes
+    }
+
+    // added are the downloads of this run, their paths have any suffix a collision gave them
+    pub fn snapshot(&self,
+                    data: &[Category],
+                    added: &[Download],
+                    selected: Vec<u64>,
+                    enabled_categories: Vec<String>)
+                    -> Snapshot {
+        // a book that wasn't added stays where an earlier run saved it, else where it would go
+        let mut paths = HashMap::new();
+        if let Some(ref previous) = self.previous {
+            for entry in previous.entries.iter() {
+                if let (Some(id), Some(path)) = (self.resolve(entry.id), entry.path.as_ref()) {
+                    paths.insert(id, path.clone());
+                }
+            }
+        }
+        for download in added.iter() {
+            paths.insert(download.id(), download.relative_path().to_string_lossy().into_owned());
+        }
+        let template = PathTemplate::from_config();
+        let mut entries = Vec::new();
+        for category in data.iter() {
+            for download in category.downloads() {
+                let path = match paths.get(&download.id()) {
+                    Some(path) => path.clone(),
+                    None => template.render(download).to_string_lossy().into_owned(),
+                };
+                entries.push(CatalogEntry {
+                    id: download.id(),
+                    title: download.name().to_owned(),
+                    url: download.url().to_owned(),
+                    category: category.name().to_owned(),
+                    path: Some(path),
+                });
+            }
+        }
+        let mut migrations = self.migrations
+                                 .iter()
+                                 .map(|(&from, &to)| Migration { from: from, to: to })
+                                 .collect::<Vec<Migration>>();
+        migrations.sort_by(|a, b| a.from.cmp(&b.from));
+        Snapshot {
+            entries: entries,
+            migrations: migrations,
+            selected: selected,
+            enabled_categories: enabled_categories,
+        }
+    }
+}
+
+// Pairs books that left the list with ones that are new to it. The same url in another
+// category is a moved book, the same title in the same category is a book whose url changed.
+fn migrate(previous: &Snapshot, data: &[Category], ids: &HashSet<u64>) -> HashMap<u64, u64> {
+    let previous_ids = previous.entries.iter().map(|e| e.id).collect::<HashSet<u64>>();
+    // (id, normalized url, title, category) of each book that's new in this list
+    let mut unclaimed = Vec::new();
+    for category in data.iter() {
+        for download in category.downloads().iter().filter(|d| !previous_ids.contains(&d.id())) {
+            unclaimed.push((download.id(),
+                            normalize_url(download.url()),
+                            download.name().to_owned(),
+                            category.name().to_owned()));
+        }
+    }
+    let mut migrations = HashMap::new();
+    for entry in previous.entries.iter().filter(|e| !ids.contains(&e.id)) {
+        let url = normalize_url(&entry.url);
+        let found = unclaimed.iter()
+                             .position(|&(_, ref new_url, _, _)| *new_url == url)
+                             .or_else(|| {
+                                 unclaimed.iter().position(|&(_, _, ref title, ref category)| {
+                                     *title == entry.title && *category == entry.category
+                                 })
+                             });
+        if let Some(idx) = found {
+            migrations.insert(entry.id, unclaimed.remove(idx).0);
+        }
+    }
+    // older renames follow the book to its newest id
+    for migration in previous.migrations.iter() {
+        let to = if ids.contains(&migration.to) {
+            Some(migration.to)
+        } else {
+            migrations.get(&migration.to).cloned()
+        };
+        if let Some(to) = to {
+            migrations.entry(migration.from).or_insert(to);
+        }
+    }
+    migrations
+}
+
+// The file a removed book was saved to, if it's still there. Books from a snapshot that
+// didn't keep paths aren't found.
+pub fn orphaned_file(change: &CatalogChange, download_dir: &Path) -> Option<PathBuf> {
+    if change.kind != ChangeKind::Removed {
+        return None;
+    }
+    let path = match change.path {
+        Some(ref path) => download_dir.join(path),
+        None => return None,
+    };
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+pub fn snapshot_path() -> PathBuf {
+    paths::data_dir().join(CATALOG_FILE_NAME)
+}
+
+// None on the first run, or if the snapshot can't be read
+pub fn load_snapshot() -> Option<Snapshot> {
+    let path = snapshot_path();
+    let mut contents = String::new();
+    if let Err(e) = File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        info!("No catalog snapshot at {:?} ({})", path, e);
+        return None;
+    }
+    match json::decode(&contents) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            warn!("Invalid catalog snapshot {:?}, ignoring it: {}", path, e);
+            None
+        }
+    }
+}
+
+pub fn save_snapshot(snapshot: &Snapshot) -> Result<(), String> {
+    let path = snapshot_path();
+    if let Some(parent) = path.parent() {
+        try!(create_dir_all(parent)
+                 .map_err(|e| format!("Failed to create data dir {:?}: {}", parent, e)));
+    }
+    let encoded = try!(json::encode(snapshot)
+                           .map_err(|e| format!("Failed to encode catalog snapshot: {}", e)));
+    write_replacing(&path, encoded.as_bytes())
+        .map_err(|e| format!("Failed to save catalog snapshot to {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use data::{Category, Download, category_path, stable_id};
+    use super::{Catalog, CatalogEntry, ChangeKind, Migration, Snapshot};
+
+    fn category(name: &str, books: &[(&str, &str)]) -> Category {
+        let mut category = Category::new(category_path(name), Vec::new());
+        for &(title, url) in books.iter() {
+            category.add_download(Download::new(title, url, None));
+        }
+        category
+    }
+
+    fn entry(title: &str, url: &str, category: &str, path: Option<&str>) -> CatalogEntry {
+        CatalogEntry {
+            id: stable_id(category, url, 0),
+            title: title.to_owned(),
+            url: url.to_owned(),
+            category: category.to_owned(),
+            path: path.map(|path| path.to_owned()),
+        }
+    }
+
+    fn snapshot(entries: Vec<CatalogEntry>, migrations: Vec<Migration>) -> Snapshot {
+        Snapshot {
+            entries: entries,
+            migrations: migrations,
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn matches_the_url_before_the_title() {
+        let old = entry("Book", "http://a.com/book.pdf", "Rust", None);
+        let data = vec![category("Rust", &[("Book", "http://b.com/book.pdf")]),
+                        category("Go", &[("Book", "http://a.com/book.pdf")])];
+        let catalog = Catalog::new(&data, Some(snapshot(vec![old.clone()], Vec::new())));
+        assert_eq!(catalog.resolve(old.id), Some(data[1].downloads()[0].id()));
+        let changes = catalog.changes(&data)
+                             .into_iter()
+                             .map(|change| (change.kind, change.category, change.previous))
+                             .collect::<Vec<_>>();
+        assert_eq!(changes,
+                   vec![(ChangeKind::Moved, "Go".to_owned(), "Rust".to_owned()),
+                        (ChangeKind::Added, "Rust".to_owned(), String::new())]);
+    }
+
+    #[test]
+    fn follows_older_migrations_to_the_newest_id() {
+        let old = entry("Book", "http://a.com/book.pdf", "Rust", None);
+        let migrations = vec![Migration {
+                                  from: 1,
+                                  to: old.id,
+                              }];
+        let mut previous = snapshot(vec![old.clone()], migrations);
+        previous.selected = vec![1];
+        let data = vec![category("Rust", &[("Book", "http://b.com/book.pdf")])];
+        let new_id = data[0].downloads()[0].id();
+        let catalog = Catalog::new(&data, Some(previous));
+        assert_eq!(catalog.resolve(old.id), Some(new_id));
+        assert_eq!(catalog.resolve(1), Some(new_id));
+        assert_eq!(catalog.previous_selection(), vec![new_id]);
+    }
+
+    #[test]
+    fn removed_books_keep_their_path() {
+        let entries = vec![entry("Gone", "http://a.com/gone.pdf", "Rust", Some("Rust/Gone.pdf")),
+                           entry("Kept", "http://a.com/kept.pdf", "Rust", Some("Rust/Kept.pdf"))];
+        let data = vec![category("Rust", &[("Kept", "http://a.com/kept.pdf")])];
+        let catalog = Catalog::new(&data, Some(snapshot(entries, Vec::new())));
+        let changes = catalog.changes(&data);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, ChangeKind::Removed);
+        assert_eq!(changes[0].title, "Gone");
+        assert_eq!(changes[0].path, Some("Rust/Gone.pdf".to_owned()));
+    }
+
+    #[test]
+    fn no_changes_on_the_first_run() {
+        let data = vec![category("Rust", &[("Book", "http://a.com/book.pdf")])];
+        assert!(Catalog::new(&data, None).changes(&data).is_empty());
+    }
+}
@@ -41,6 +41,15 @@ pub trait AddCellRenderers {
                                   add_mode: AddMode,
                                   column_number: i32)
         -> gtk::CellRendererToggle;
+    fn add_progress_text_renderer_column(&self,
+                                         title: &str,
+                                         fill: bool,
+                                         resizable: bool,
+                                         expand: bool,
+                                         add_mode: AddMode,
+                                         value_column: i32,
+                                         text_column: i32,
+                                         visible_column: i32);
 }
 
 impl AddCellRenderers for gtk::TreeView {
@@ -129,4 +138,33 @@ impl AddCellRenderers for gtk::TreeView {
                                           column_number);
             cell
         }
+
+    // progress bar with its own label, only drawn on rows where visible_column is set
+    fn add_progress_text_renderer_column(&self,
+                                         title: &str,
+                                         fill: bool,
+                                         resizable: bool,
+                                         expand: bool,
+                                         add_mode: AddMode,
+                                         value_column: i32,
+                                         text_column: i32,
+                                         visible_column: i32) {
+        let cell = gtk::CellRendererProgress::new();
+        let column = gtk::TreeViewColumn::new();
+        match add_mode {
+            AddMode::PackEnd => {
+                column.pack_end(&cell, fill);
+            }
+            AddMode::PackStart => {
+                column.pack_start(&cell, fill);
+            }
+        }
+        column.add_attribute(&cell, "value", value_column);
+        column.add_attribute(&cell, "text", text_column);
+        column.add_attribute(&cell, "visible", visible_column);
+        column.set_title(title);
+        column.set_resizable(resizable);
+        column.set_expand(expand);
+        self.append_column(&column);
+    }
 }
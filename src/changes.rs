@@ -0,0 +1,111 @@
+use std::path::Path;
+use gtk;
+use gtk::prelude::*;
+use glib::types::Type;
+use catalog::{CatalogChange, ChangeKind, orphaned_file};
+use cellrenderers::*;
+
+const DOWNLOAD_NEW_RESPONSE: i32 = 1;
+const FLAG_ORPHANS_RESPONSE: i32 = 2;
+
+// change, category, title, before, then the local file of a removed book once flagged
+const COL_LOCAL_FILE: u32 = 4;
+
+// Lists what changed in the book list since the last run. download_new gets the ids of the
+// new books and returns how many of them it queued.
+pub fn run_changes<F>(parent: &gtk::Window,
+                      changes: &[CatalogChange],
+                      download_dir: &Path,
+                      download_new: F)
+    where F: Fn(&[u64]) -> usize
+{
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Changes since the last run");
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.set_default_size(900, 500);
+    dialog.add_buttons(&[("Download new books", DOWNLOAD_NEW_RESPONSE),
+                         ("Flag orphaned files", FLAG_ORPHANS_RESPONSE),
+                         ("Close", gtk::ResponseType::Close as i32)]);
+
+    let store = gtk::ListStore::new(&[Type::String, Type::String, Type::String, Type::String,
+                                      Type::String]);
+    for change in changes.iter() {
+        let iter = store.append();
+        store.set_value(&iter, 0, &change.kind.label().to_value());
+        store.set_value(&iter, 1, &change.category.to_value());
+        store.set_value(&iter, 2, &change.title.to_value());
+        store.set_value(&iter, 3, &change.previous.to_value());
+        store.set_value(&iter, COL_LOCAL_FILE, &"".to_value());
+    }
+    let view = gtk::TreeView::new();
+    view.set_model(Some(&store));
+    view.add_text_renderer_column("Change", true, true, false, AddMode::PackStart, false, 0);
+    view.add_text_renderer_column("Category", true, true, false, AddMode::PackStart, true, 1);
+    view.add_text_renderer_column("Title", true, true, true, AddMode::PackStart, true, 2);
+    view.add_text_renderer_column("Before", true, true, false, AddMode::PackStart, true, 3);
+    view.add_text_renderer_column("Local file", true, true, false, AddMode::PackStart, true, 4);
+    let scroll = gtk::ScrolledWindow::new(None, None);
+    scroll.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+    scroll.add(&view);
+
+    let status = gtk::Label::new(Some(&summarize(changes)));
+    status.set_halign(gtk::Align::Start);
+    if let Some(content_area) = dialog.get_content_area() {
+        content_area.pack_start(&status, false, false, 5);
+        content_area.pack_start(&scroll, true, true, 0);
+    }
+    dialog.show_all();
+
+    let added = changes.iter()
+                       .filter(|change| change.kind == ChangeKind::Added)
+                       .map(|change| change.id)
+                       .collect::<Vec<u64>>();
+    loop {
+        let response = dialog.run();
+        if response == DOWNLOAD_NEW_RESPONSE {
+            let queued = download_new(&added);
+            status.set_text(&format!("Queued {} of {} new books, the rest aren't in a category \
+                                      you download from",
+                                     queued,
+                                     added.len()));
+        } else if response == FLAG_ORPHANS_RESPONSE {
+            let mut orphans = 0;
+            if let Some(mut iter) = store.get_iter_first() {
+                for change in changes.iter() {
+                    if let Some(path) = orphaned_file(change, download_dir) {
+                        warn!("{:?} was removed from the list", path);
+                        store.set_value(&iter, COL_LOCAL_FILE, &path.to_string_lossy().to_value());
+                        orphans += 1;
+                    }
+                    if !store.iter_next(&mut iter) {
+                        break;
+                    }
+                }
+            }
+            status.set_text(&format!("{} downloaded files are no longer in the list", orphans));
+        } else {
+            break;
+        }
+    }
+    dialog.destroy();
+}
+
+// "3 new, 1 removed, 2 moved"
+fn summarize(changes: &[CatalogChange]) -> String {
+    let kinds = [ChangeKind::Added,
+                 ChangeKind::Removed,
+                 ChangeKind::Moved,
+                 ChangeKind::UrlChanged,
+                 ChangeKind::Renamed];
+    let counts = kinds.iter()
+                      .map(|&kind| (kind, changes.iter().filter(|c| c.kind == kind).count()))
+                      .filter(|&(_, count)| count > 0)
+                      .map(|(kind, count)| format!("{} {}", count, kind.label().to_lowercase()))
+                      .collect::<Vec<String>>();
+    if counts.is_empty() {
+        "The list hasn't changed since the last run".to_owned()
+    } else {
+        counts.join(", ")
+    }
+}
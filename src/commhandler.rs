@@ -1,16 +1,17 @@
 use data::*;
-use std::sync::mpsc::{channel, Sender, Receiver, SendError};
+use std::sync::mpsc::{channel, Sender, Receiver};
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 use std::collections::{HashMap, VecDeque};
 use threadpool::ThreadPool;
 use downloader::*;
-use helper::{Ignore, name_to_fname, name_to_dname};
+use helper::{Ignore, ToByteUnits, tmp_path, normalize_url};
+use pathtemplate::{PathTemplate, with_suffix, same_file};
+use sanitize::{sanitize_component, contained_path};
 use gui::update_gui;
 use time::precise_time_ns;
-use constants::GUI_UPDATE_TIME;
+use config;
 use std::fs::create_dir_all;
-use std::ffi::OsStr;
 use fsthread::*;
 
 pub struct CommHandler {
@@ -39,6 +40,13 @@ pub struct CommHandler {
     fsthread_recv: Receiver<FsUpdate>,
     threadpool_cmd_send: Vec<Sender<TpoolCmdMsg>>,
     next_gui_update_t: u64,
+    // id:id of the download with the same url it gets its file from
+    mirrors: HashMap<u64, u64>,
+    // id:files to delete once its downloader has exited
+    pending_deletes: HashMap<u64, Vec<PathBuf>>,
+    // lowercased path:ids of every book under the template it was rendered with, built
+    // the first time a file is found on disk and again once the template or the list changes
+    rendered_paths: Option<(PathTemplate, HashMap<String, Vec<u64>>)>,
 }
 
 impl CommHandler {
@@ -68,7 +76,10 @@ impl CommHandler {
             fsthread_send: fsthread_send,
             fsthread_recv: fsthread_recv,
             threadpool_cmd_send: Vec::new(),
-            next_gui_update_t: precise_time_ns() + GUI_UPDATE_TIME,
+            next_gui_update_t: precise_time_ns() + gui_update_time(),
+            mirrors: HashMap::new(),
+            pending_deletes: HashMap::new(),
+            rendered_paths: None,
         }
     }
 
@@ -100,9 +111,23 @@ impl CommHandler {
         // start execution of any jobs that exist
         let max_threads = self.max_threads.lock().unwrap().clone();
         let current_threads = self.current_threads.lock().unwrap().clone();
-        if !self.jobs.is_empty() && (max_threads > current_threads) {
-            let job = self.jobs.pop_front().unwrap();
+        // a file shared by several books is only written by one downloader at a time
+        let next_job = if max_threads > current_threads {
+            self.jobs.iter().position(|job| !self.file_in_use(job))
+        } else {
+            None
+        };
+        if let Some(job_idx) = next_job {
+            let job = self.jobs.remove(job_idx).unwrap();
+            if let Some(download) = self.data.get_mut(&job.id()) {
+                download.set_started();
+                if let Some(idx) = self.current_ids.iter().position(|&id| id == job.id()) {
+                    self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                }
+            }
             let progress_sender = self.threadpool_progress_send.clone();
+            let exit_sender = self.threadpool_progress_send.clone();
+            let job_id = job.id();
             let (tchan_cmd_s, tchan_cmd_r) = channel();
             self.threadpool_cmd_send.push(tchan_cmd_s);
             let mut downloader = Downloader::new(job, tchan_cmd_r, progress_sender);
@@ -131,7 +156,7 @@ impl CommHandler {
                                 *current_threads.lock().unwrap() -= 1;
                                 keep_downloading = false;
                                 match &e as &str {
-                                    "finished" | "stopped" => {}
+                                    "finished" | "stopped" | "paused" => {}
                                     _ => {
                                         downloader.send_panicked(e.to_owned());
                                     }
@@ -140,6 +165,7 @@ impl CommHandler {
                         }
                     }
                     drop(downloader);
+                    exit_sender.send((job_id, DownloadUpdate::Exited)).ignore();
                 });
             }
         }
@@ -163,15 +189,17 @@ impl CommHandler {
             }
             // clear datacache
             self.datacache.clear();
+            let summary = self.summary();
+            self.pending_changes.push(GuiChange::Summary(summary));
             // send the changes
             if let Err(e) = self.gui_update_send.send(self.pending_changes.to_owned()) {
                 if e.description() != "sending on a closed channel" {
-                    println!("Failed to send gui update message: {}", e);
+                    error!("Failed to send gui update message: {}", e);
                 }
             }
             // clear pending changes
             self.pending_changes.clear();
-            self.next_gui_update_t = current_time + GUI_UPDATE_TIME;
+            self.next_gui_update_t = current_time + gui_update_time();
             update_gui()
         }
 
@@ -184,35 +212,57 @@ impl CommHandler {
 
     fn handle_gui_cmd(&mut self, cmd: GuiCmdMsg) {
         match cmd {
-            GuiCmdMsg::Add(id, path) => {
-                let mut download = self.data.get_mut(&id).unwrap();
-                download.start_download();
-                download.set_enable_state(true);
-                download.set_path(path);
-                // add to jobs
-                self.jobs.push_back(download.clone());
-                self.current_ids.push(id);
-                // add to pending changes
-                self.pending_changes.push(GuiChange::Add(download.to_owned()));
+            GuiCmdMsg::Add(id, path) => self.add(id, path, false),
+            GuiCmdMsg::AddPaused(id, path) => self.add(id, path, true),
+            GuiCmdMsg::Extend(downloads) => {
+                let mut added = Vec::new();
+                for download in downloads {
+                    if !self.data.contains_key(&download.id()) {
+                        added.push(download.clone());
+                        self.data.insert(download.id(), download);
+                    }
+                }
+                if !added.is_empty() {
+                    self.rendered_paths = None;
+                    self.pending_changes.push(GuiChange::Extend(added));
+                }
+            }
+            GuiCmdMsg::ResolveCollision(id, policy) => {
+                self.resolve_collision(id, policy);
             }
             GuiCmdMsg::Restart(idx) => {
                 let id = self.current_ids[idx];
-                let mut download = self.data.get_mut(&id).unwrap();
-                download.start_download();
-                download.set_enable_state(true);
-                self.jobs.push_front(download.clone());
-                self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                // its downloader would keep writing the file the new one starts over
+                if self.data[&id].state() == Some(DownloadState::Downloading) {
+                    return;
+                }
+                {
+                    let mut download = self.data.get_mut(&id).unwrap();
+                    download.restart_download();
+                    download.set_enable_state(true);
+                    download.set_resume(false);
+                    self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                }
+                self.requeue(id);
             }
             GuiCmdMsg::Open(idx) => {
                 let id = self.current_ids[idx];
                 let download = self.data.get(&id).unwrap();
                 self.pending_changes
-                    .push(GuiChange::Open(download.path()
-                                                  .join(name_to_fname(download.name()))
+                    .push(GuiChange::Open(download.file_path()
                                                   .to_str()
                                                   .unwrap()
                                                   .to_owned()));
             }
+            GuiCmdMsg::OpenFile(idx) => {
+                let id = self.current_ids[idx];
+                let download = self.data.get(&id).unwrap();
+                self.pending_changes
+                    .push(GuiChange::OpenFile(download.file_path()
+                                                      .to_str()
+                                                      .unwrap()
+                                                      .to_owned()));
+            }
             GuiCmdMsg::Remove(id) => {
                 let mut in_jobs = false;
                 // remove from jobs if existing
@@ -240,55 +290,138 @@ impl CommHandler {
                 }
                 // broadcast to all threads
                 if !in_jobs {
-                    self.broadcast(TpoolCmdMsg::Remove(id)).ignore();
+                    self.broadcast(TpoolCmdMsg::Remove(id));
                 }
+                self.release_mirrors(id);
             }
             GuiCmdMsg::Cancel(idx) => {
+                self.cancel(idx);
+            }
+            GuiCmdMsg::Pause(idx) => {
                 let id = self.current_ids[idx];
-                let mut in_jobs = false;
-                for i in 0..self.jobs.len() {
-                    if self.jobs[i].id() == id {
-                        self.jobs.remove(idx);
-                        in_jobs = true;
-                        break;
-                    }
-                }
-
-                self.current_ids.remove(idx);
-                self.pending_changes.push(GuiChange::Remove(idx));
-
+                let in_jobs = self.remove_job(id);
                 {
-                    let mut dl = self.data.get_mut(&id).unwrap();
-                    dl.set_enable_state(false);
-                    dl.stop_download();
+                    let download = self.data.get_mut(&id).unwrap();
+                    download.set_state(DownloadState::Paused);
+                    self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
                 }
-
-                // broadcast to all threads
+                // a running downloader keeps its .tmp file to resume from
                 if !in_jobs {
-                    self.broadcast(TpoolCmdMsg::Remove(id)).ignore();
+                    self.broadcast(TpoolCmdMsg::Pause(id));
                 }
-
+            }
+            GuiCmdMsg::Resume(idx) => {
+                let id = self.current_ids[idx];
+                if self.data[&id].state() == Some(DownloadState::Paused) {
+                    {
+                        let download = self.data.get_mut(&id).unwrap();
+                        download.set_state(DownloadState::Queued);
+                        // picks up the .tmp file the pause left
+                        download.set_resume(true);
+                        self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                    }
+                    self.requeue(id);
+                }
+            }
+            GuiCmdMsg::MoveToTop(idx) => {
+                let id = self.current_ids[idx];
+                if let Some(job_idx) = self.jobs.iter().position(|job| job.id() == id) {
+                    let job = self.jobs.remove(job_idx).unwrap();
+                    self.jobs.push_front(job);
+                }
+            }
+            GuiCmdMsg::MoveToBottom(idx) => {
+                let id = self.current_ids[idx];
+                if let Some(job_idx) = self.jobs.iter().position(|job| job.id() == id) {
+                    let job = self.jobs.remove(job_idx).unwrap();
+                    self.jobs.push_back(job);
+                }
+            }
+            GuiCmdMsg::Verify(idx) => {
+                let id = self.current_ids[idx];
+                let download = &self.data[&id];
+                let expected = download.download_info().as_ref().map(|i| i.total()).unwrap_or(0);
+                self.fsthread_send
+                    .send(FsCommand::Verify(id, download.file_path(), expected))
+                    .expect("FsThread send fail");
+            }
+            GuiCmdMsg::DeleteFile(idx) => {
+                let id = self.current_ids[idx];
+                let path = self.data[&id].file_path();
+                let paths = vec![tmp_path(&path), path];
+                let running = self.data[&id].state() == Some(DownloadState::Downloading);
+                self.cancel(idx);
+                // a running downloader can still write or rename its file until it has
+                // seen the cancel
+                if running {
+                    self.pending_deletes.insert(id, paths);
+                } else {
+                    self.delete_files(paths);
+                }
+            }
+            GuiCmdMsg::Batch(action, ids) => {
+                debug!("{:?} on {} downloads", action, ids.len());
+                for id in ids {
+                    // look the index up each time, earlier removals shift it
+                    if let Some(idx) = self.current_ids.iter().position(|&other| other == id) {
+                        let state = self.data[&id].state();
+                        let cmd = match action {
+                            BatchAction::Cancel => GuiCmdMsg::Cancel(idx),
+                            BatchAction::Restart => GuiCmdMsg::Restart(idx),
+                            BatchAction::Pause => GuiCmdMsg::Pause(idx),
+                            BatchAction::Resume => GuiCmdMsg::Resume(idx),
+                            BatchAction::Remove => GuiCmdMsg::Remove(id),
+                            BatchAction::Open => {
+                                if state == Some(DownloadState::Finished) {
+                                    GuiCmdMsg::OpenFile(idx)
+                                } else {
+                                    GuiCmdMsg::Open(idx)
+                                }
+                            }
+                        };
+                        let applies = match action {
+                            BatchAction::Pause => {
+                                state == Some(DownloadState::Queued) ||
+                                state == Some(DownloadState::Downloading)
+                            }
+                            BatchAction::Resume => state == Some(DownloadState::Paused),
+                            BatchAction::Restart => {
+                                state != Some(DownloadState::Queued) &&
+                                state != Some(DownloadState::Downloading)
+                            }
+                            _ => true,
+                        };
+                        if applies {
+                            self.handle_gui_cmd(cmd);
+                        }
+                    }
+                }
+            }
+            GuiCmdMsg::RetryFailed => {
+                let failed = self.ids_in_state(DownloadState::Failed);
+                info!("Retrying {} failed downloads", failed.len());
+                self.handle_gui_cmd(GuiCmdMsg::Batch(BatchAction::Restart, failed));
+            }
+            GuiCmdMsg::ClearFinished => {
+                let finished = self.ids_in_state(DownloadState::Finished);
+                self.handle_gui_cmd(GuiCmdMsg::Batch(BatchAction::Remove, finished));
             }
             GuiCmdMsg::SetThreads(threads) => {
+                debug!("Setting max threads to {}", threads);
                 *self.max_threads.lock().unwrap() = threads;
                 self.threadpool.set_threads(threads);
             }
             GuiCmdMsg::ChangeDir(newdir) => {
+                info!("Changing download directory to {:?}", newdir);
                 // Copy over all of the finished downloads
                 for id in self.current_ids.iter() {
                     let mut dl = self.data.get_mut(id).unwrap();
                     if dl.finished() {
-                        let fname = name_to_fname(dl.name());
-                        let oldpath = dl.path().join(fname.to_owned());
-                        let newpath;
-                        if let Some(ref category_name) = dl.category_name() {
-                            let category_dir = newdir.to_owned()
-                                                     .join(name_to_dname(category_name));
-                            create_dir_all(&category_dir).expect("Failed to create dir");
-                            newpath = category_dir.join(fname);
-                        } else {
-                            newpath = newdir.to_owned()
-                                            .join(fname);
+                        let oldpath = dl.file_path();
+                        dl.set_path(newdir.to_owned());
+                        let newpath = dl.file_path();
+                        if let Some(parent) = newpath.parent() {
+                            create_dir_all(parent).expect("Failed to create dir");
                         }
                         self.fsthread_send
                             .send(FsCommand::Copy(oldpath.clone(), newpath.clone()))
@@ -301,26 +434,13 @@ impl CommHandler {
                 }
                 // change all of the pending jobs to the new path
                 for job in self.jobs.iter_mut() {
-                    let current_path = job.path();
-                    let category_name = job.category_name();
-                    let backup_fname = name_to_fname(job.name());
-                    let current_fname = current_path.file_name()
-                                                    .unwrap_or(OsStr::new(&backup_fname));
-                    let newpath;
-                    if let Some(category) = category_name {
-                        newpath = newdir.to_owned()
-                                        .join(name_to_dname(&category))
-                                        .join(current_fname);
-                    } else {
-                        newpath = newdir.to_owned().join(current_fname);
-                    }
-                    job.set_path(newpath);
+                    job.set_path(newdir.to_owned());
                 }
                 // broadcast to downloaders
-                self.broadcast(TpoolCmdMsg::ChangeDir(newdir)).ignore();
+                self.broadcast(TpoolCmdMsg::ChangeDir(newdir));
             }
             GuiCmdMsg::Stop => {
-                self.broadcast(TpoolCmdMsg::Stop).ignore();
+                self.broadcast(TpoolCmdMsg::Stop);
                 self.fsthread_send.send(FsCommand::Stop).ignore();
                 drop(self)
             }
@@ -340,60 +460,503 @@ impl CommHandler {
                     }
                 }
             }
+            DownloadUpdate::Response(response) => {
+                let mut download = self.data.get_mut(&id).unwrap();
+                download.set_response(response);
+                if let Some(idx) = self.current_ids.iter().position(|&other| other == id) {
+                    self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                }
+            }
+            DownloadUpdate::Resumed(offset) => {
+                // anything cached is from before the pause and already on disk
+                self.datacache.remove(&id);
+                let download = self.data.get_mut(&id).unwrap();
+                download.set_progress(offset);
+            }
             DownloadUpdate::Amount(amount) => {
                 // add to cache
                 self.datacache.increment(id, amount);
             }
             DownloadUpdate::Finished => {
-                let mut download = self.data.get_mut(&id).unwrap();
-                download.set_finished();
-                for idx in 0..self.current_ids.len() {
-                    if self.current_ids[idx] == id {
-                        // remove any other sets
-                        for i in (0..self.pending_changes.len()).rev() {
-                            if let GuiChange::Set(otheridx, _) = self.pending_changes[i] {
-                                if otheridx == idx {
-                                    self.pending_changes.remove(i);
+                {
+                    let mut download = self.data.get_mut(&id).unwrap();
+                    download.set_finished();
+                    for idx in 0..self.current_ids.len() {
+                        if self.current_ids[idx] == id {
+                            // remove any other sets
+                            for i in (0..self.pending_changes.len()).rev() {
+                                if let GuiChange::Set(otheridx, _) = self.pending_changes[i] {
+                                    if otheridx == idx {
+                                        self.pending_changes.remove(i);
+                                    }
                                 }
                             }
+                            self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                            break;
                         }
-                        self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
-                        break;
                     }
                 }
+                // the same book in other categories
+                let mirrors = self.mirrors
+                                  .iter()
+                                  .filter(|&(_, &source)| source == id)
+                                  .map(|(&mirror, _)| mirror)
+                                  .collect::<Vec<u64>>();
+                for mirror in mirrors {
+                    self.link_mirror(mirror);
+                }
+            }
+            DownloadUpdate::Exited => {
+                if let Some(paths) = self.pending_deletes.remove(&id) {
+                    self.delete_files(paths);
+                }
             }
             DownloadUpdate::Message(msg) => {
-                println!("{}", msg);
+                info!("{}: {}", self.data[&id].name(), msg);
             }
             DownloadUpdate::Panicked(error) => {
-                let ref download = self.data[&id];
-                let mut newerr = download.name().to_owned() + ": ";
-                newerr.push_str(&error);
-                self.pending_changes.push(GuiChange::Panicked(true, newerr));
+                let retries = config::with(|c| c.retries);
+                // cancelled while its thread was still running, there's nothing left to retry
+                let idx = match self.current_ids.iter().position(|&other| other == id) {
+                    Some(idx) if self.data[&id].download_info().is_some() => idx,
+                    _ => {
+                        debug!("Dropping the error of a removed download: {}", error);
+                        return;
+                    }
+                };
+                {
+                    let download = self.data.get_mut(&id).unwrap();
+                    download.set_failed(error.clone());
+                    let attempts = download.download_info()
+                                           .as_ref()
+                                           .map(|i| i.attempts())
+                                           .unwrap_or(0);
+                    if attempts <= retries {
+                        // queue it again at the back, only report once out of retries
+                        info!("Retrying {} ({} of {}): {}",
+                              download.name(),
+                              attempts,
+                              retries,
+                              error);
+                        download.restart_download();
+                        download.set_enable_state(true);
+                        download.set_resume(false);
+                        self.jobs.push_back(download.clone());
+                        self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                        return;
+                    }
+                    self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                    let mut newerr = download.name().to_owned() + ": ";
+                    newerr.push_str(&error);
+                    self.pending_changes.push(GuiChange::Panicked(true, newerr));
+                }
+                // another listing of the same url gets a go at it
+                self.release_mirrors(id);
+            }
+        }
+    }
+
+    // totals over the current list, read straight from the model
+    fn summary(&self) -> QueueSummary {
+        let mut summary = QueueSummary::default();
+        let mut sized = 0;
+        let mut unsized_remaining = 0;
+        let mut remaining = 0;
+        for id in self.current_ids.iter() {
+            let download = &self.data[id];
+            if let &Some(ref info) = download.download_info() {
+                summary.downloaded += info.progress();
+                summary.known_total += info.total();
+                if info.total() > 0 {
+                    sized += 1;
+                }
+                match info.state() {
+                    DownloadState::Queued => summary.queued += 1,
+                    DownloadState::Downloading => {
+                        summary.active += 1;
+                        summary.speed += info.speed();
+                    }
+                    DownloadState::Paused => summary.paused += 1,
+                    DownloadState::Finished => summary.done += 1,
+                    DownloadState::Failed => summary.failed += 1,
+                }
+                // paused downloads don't count towards the queue finishing
+                if info.state() == DownloadState::Queued ||
+                   info.state() == DownloadState::Downloading {
+                    if info.total() > 0 {
+                        remaining += info.total().saturating_sub(info.progress());
+                    } else {
+                        unsized_remaining += 1;
+                    }
+                }
+            }
+        }
+        // jobs without a known size yet are assumed to be as large as the average known one
+        if sized > 0 {
+            remaining += unsized_remaining * (summary.known_total / sized);
+        }
+        if summary.speed > 0.0 && (sized > 0 || unsized_remaining == 0) {
+            summary.eta = Some(remaining as f64 / summary.speed as f64);
+        }
+        summary
+    }
+
+    // also drops the channels of downloaders that have exited
+    fn broadcast(&mut self, msg: TpoolCmdMsg) {
+        self.threadpool_cmd_send.retain(|channel| channel.send(msg.clone()).is_ok());
+    }
+
+    fn cancel(&mut self, idx: usize) {
+        let id = self.current_ids[idx];
+        let in_jobs = self.remove_job(id);
+
+        self.current_ids.remove(idx);
+        self.pending_changes.push(GuiChange::Remove(idx));
+
+        {
+            let mut dl = self.data.get_mut(&id).unwrap();
+            dl.set_enable_state(false);
+            dl.stop_download();
+        }
+
+        // broadcast to all threads
+        if !in_jobs {
+            self.broadcast(TpoolCmdMsg::Remove(id));
+        }
+        self.release_mirrors(id);
+    }
+
+    // lists the download and queues it unless it's paused
+    fn add(&mut self, id: u64, path: PathBuf, paused: bool) {
+        // already listed, e.g. enabled from two categories at once
+        if self.current_ids.contains(&id) {
+            return;
+        }
+        let relative_path = {
+            let download = &self.data[&id];
+            let relative_path = PathTemplate::from_config().render(download);
+            match contained_path(&path, &relative_path) {
+                Ok(_) => relative_path,
+                Err(e) => {
+                    warn!("{}, saving {} as its title instead", e, download.url());
+                    PathBuf::from(sanitize_component(download.name()))
+                }
+            }
+        };
+        {
+            let mut download = self.data.get_mut(&id).unwrap();
+            download.start_download();
+            download.set_enable_state(true);
+            download.set_path(path);
+            download.set_relative_path(relative_path);
+            download.set_overwrite(false);
+            download.set_resume(false);
+            if paused {
+                download.set_state(DownloadState::Paused);
+            }
+            self.current_ids.push(id);
+            // add to pending changes
+            self.pending_changes.push(GuiChange::Add(download.to_owned()));
+        }
+        match self.colliding_download(id) {
+            None => self.queue(id),
+            Some(other) => {
+                let taken_by = format!("\"{}\" already uses", self.data[&other].name());
+                self.handle_collision(id, taken_by);
+            }
+        }
+    }
+
+    // applies the collision policy to a book whose file is taken, taken_by ends the question
+    fn handle_collision(&mut self, id: u64, taken_by: String) {
+        match config::with(|c| c.collision_policy) {
+            CollisionPolicy::Ask => {
+                let download = &self.data[&id];
+                let question = format!("\"{}\" would be saved as {:?}, which {}.",
+                                       download.name(),
+                                       download.file_path(),
+                                       taken_by);
+                self.pending_changes.push(GuiChange::Collision(id, question));
+            }
+            policy => self.resolve_collision(id, policy),
+        }
+    }
+
+    fn delete_files(&self, paths: Vec<PathBuf>) {
+        for path in paths {
+            if path.exists() {
+                info!("Deleting {:?}", path);
+                self.fsthread_send
+                    .send(FsCommand::Remove(path))
+                    .expect("FsThread send fail");
+            }
+        }
+    }
+
+    fn ids_in_state(&self, state: DownloadState) -> Vec<u64> {
+        self.current_ids
+            .iter()
+            .cloned()
+            .filter(|id| self.data[id].state() == Some(state))
+            .collect()
+    }
+
+    // downloads it, or waits for another download of the same url to link its file
+    fn queue(&mut self, id: u64) {
+        match self.source_download(id) {
+            Some(source) => {
+                debug!("{} is fetched by {}", self.data[&id].name(), self.data[&source].name());
+                self.mirrors.insert(id, source);
+                if self.data[&source].finished() {
+                    self.link_mirror(id);
+                }
+            }
+            // a paused download joins the queue once it's resumed
+            None if self.data[&id].state() == Some(DownloadState::Paused) => {}
+            None => {
+                let job = self.data[&id].clone();
+                self.jobs.push_back(job);
+            }
+        }
+    }
+
+    // like queue, but at the front for restarted and resumed downloads
+    fn requeue(&mut self, id: u64) {
+        match self.mirrors.get(&id).cloned() {
+            Some(source) => {
+                if self.data[&source].finished() {
+                    self.link_mirror(id);
+                }
+            }
+            None => {
+                let job = self.data[&id].clone();
+                self.jobs.push_front(job);
             }
         }
     }
 
-    fn broadcast(&self, msg: TpoolCmdMsg) -> Result<(), SendError<TpoolCmdMsg>> {
-        for channel in self.threadpool_cmd_send.iter() {
-            let sendresult = channel.send(msg.clone());
-            if sendresult.is_err() {
-                return sendresult;
+    // another added download of the same url that fetches the file itself
+    fn source_download(&self, id: u64) -> Option<u64> {
+        let url = normalize_url(self.data[&id].url());
+        self.current_ids.iter().cloned().find(|&other| {
+            other != id && !self.mirrors.contains_key(&other) &&
+            self.data[&other].state() != Some(DownloadState::Failed) &&
+            normalize_url(self.data[&other].url()) == url
+        })
+    }
+
+    fn link_mirror(&mut self, id: u64) {
+        let source = self.mirrors[&id];
+        let source_path = self.data[&source].file_path();
+        let path = self.data[&id].file_path();
+        if same_file(&source_path, &path) {
+            self.finish_mirror(id, Ok(()));
+        } else {
+            let mode = config::with(|c| c.duplicate_link);
+            let overwrite = self.data[&id].overwrite();
+            self.fsthread_send
+                .send(FsCommand::Link(id, source_path, path, mode, overwrite))
+                .expect("FsThread send fail");
+        }
+    }
+
+    fn finish_mirror(&mut self, id: u64, result: Result<(), String>) {
+        let total = match self.mirrors.get(&id) {
+            Some(source) => {
+                self.data[source].download_info().as_ref().map(|info| info.total()).unwrap_or(0)
+            }
+            None => return,
+        };
+        let idx = self.current_ids.iter().position(|&other| other == id);
+        let download = self.data.get_mut(&id).unwrap();
+        match result {
+            Ok(_) => {
+                info!("Linked {:?}", download.file_path());
+                download.set_total(total);
+                download.set_progress(total);
+                download.set_finished();
+            }
+            Err(e) => {
+                warn!("Failed to link {:?}: {}", download.file_path(), e);
+                download.set_failed(e);
+            }
+        }
+        if let Some(idx) = idx {
+            self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+        }
+    }
+
+    // the source of some mirrors is going away, the first of them fetches the file instead
+    fn release_mirrors(&mut self, source: u64) {
+        self.mirrors.remove(&source);
+        let mirrors = self.current_ids
+                          .iter()
+                          .cloned()
+                          .filter(|id| self.mirrors.get(id) == Some(&source))
+                          .collect::<Vec<u64>>();
+        if let Some((&new_source, rest)) = mirrors.split_first() {
+            self.mirrors.remove(&new_source);
+            for id in rest {
+                self.mirrors.insert(*id, new_source);
+            }
+            if self.data[&new_source].state() == Some(DownloadState::Queued) {
+                self.requeue(new_source);
+            }
+        }
+    }
+
+    // Another book of a different url that writes the same file. That's an added one, or
+    // when the file is already there, any book of the list that would be saved to it, e.g.
+    // in an earlier run. A file no other book resolves to is this one's and is kept.
+    fn colliding_download(&mut self, id: u64) -> Option<u64> {
+        let (url, file_path, relative_path) = {
+            let download = &self.data[&id];
+            (normalize_url(download.url()),
+             download.file_path(),
+             download.relative_path().to_string_lossy().to_lowercase())
+        };
+        let added = self.current_ids
+                        .iter()
+                        .cloned()
+                        .find(|&other| {
+                            other != id && normalize_url(self.data[&other].url()) != url &&
+                            same_file(&self.data[&other].file_path(), &file_path)
+                        });
+        if added.is_some() || !file_path.exists() {
+            return added;
+        }
+        let template = PathTemplate::from_config();
+        let stale = match self.rendered_paths {
+            Some((ref rendered_with, _)) => *rendered_with != template,
+            None => true,
+        };
+        if stale {
+            let mut paths: HashMap<String, Vec<u64>> = HashMap::new();
+            for other in self.data.values() {
+                paths.entry(template.render(other).to_string_lossy().to_lowercase())
+                     .or_insert(Vec::new())
+                     .push(other.id());
+            }
+            self.rendered_paths = Some((template, paths));
+        }
+        let candidates = match self.rendered_paths {
+            Some((_, ref paths)) => paths.get(&relative_path).cloned().unwrap_or(Vec::new()),
+            None => Vec::new(),
+        };
+        candidates.into_iter().find(|&other| {
+            other != id && !self.current_ids.contains(&other) &&
+            normalize_url(self.data[&other].url()) != url
+        })
+    }
+
+    // whether another downloader is writing the file this job would write
+    fn file_in_use(&self, job: &Download) -> bool {
+        let file_path = job.file_path();
+        self.current_ids.iter().any(|&other| {
+            other != job.id() &&
+            self.data[&other].state() == Some(DownloadState::Downloading) &&
+            same_file(&self.data[&other].file_path(), &file_path)
+        })
+    }
+
+    fn resolve_collision(&mut self, id: u64, policy: CollisionPolicy) {
+        // it may have been removed or paused while the user was asked
+        let idx = match self.current_ids.iter().position(|&other| other == id) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let state = self.data[&id].state();
+        if state != Some(DownloadState::Queued) && state != Some(DownloadState::Paused) {
+            return;
+        }
+        match policy {
+            CollisionPolicy::Skip => {
+                // a mirror isn't linked when its source finishes again
+                self.mirrors.remove(&id);
+                let download = self.data.get_mut(&id).unwrap();
+                let error = format!("Skipped, {:?} is already used by another book",
+                                    download.file_path());
+                info!("{}: {}", download.name(), error);
+                download.set_failed(error);
+                self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                return;
+            }
+            CollisionPolicy::Overwrite => {
+                self.data.get_mut(&id).unwrap().set_overwrite(true);
             }
+            CollisionPolicy::Suffix | CollisionPolicy::Ask => {
+                let relative_path = self.data[&id].relative_path();
+                let mut n = 2;
+                loop {
+                    let suffixed = with_suffix(&relative_path, n);
+                    self.data.get_mut(&id).unwrap().set_relative_path(suffixed);
+                    if self.colliding_download(id).is_none() {
+                        break;
+                    }
+                    n += 1;
+                }
+            }
+        }
+        let download = self.data[&id].clone();
+        self.pending_changes.push(GuiChange::Set(idx, download));
+        self.queue(id);
+    }
+
+    // returns whether it was still waiting in the queue
+    fn remove_job(&mut self, id: u64) -> bool {
+        if let Some(job_idx) = self.jobs.iter().position(|job| job.id() == id) {
+            self.jobs.remove(job_idx);
+            true
+        } else {
+            false
         }
-        Ok(())
     }
 
     fn handle_fsthread_update(&mut self, update: FsUpdate) {
         match update {
             FsUpdate::Error(msg) => {
+                error!("FsThread error: {}", msg);
                 self.pending_changes
                     .push(GuiChange::Panicked(true, format!("FsThread error: {}", msg)));
             }
+            FsUpdate::Linked(id, result) => {
+                self.finish_mirror(id, result);
+            }
+            FsUpdate::Taken(id) => {
+                self.handle_collision(id, "already holds a different file".to_owned());
+            }
+            FsUpdate::Verified(id, result) => {
+                let idx = self.current_ids.iter().position(|&other| other == id);
+                let download = self.data.get_mut(&id).unwrap();
+                match result {
+                    Ok(size) => {
+                        info!("Verified {:?} ({} bytes)", download.file_path(), size);
+                        self.pending_changes.push(GuiChange::Notice(format!("{}: verified, {}",
+                                                                            download.name(),
+                                                                            (size as f32).convert_to_byte_units(1))));
+                    }
+                    Err(e) => {
+                        warn!("Verification of {:?} failed: {}", download.file_path(), e);
+                        download.set_failed(format!("Verification failed: {}", e));
+                        if let Some(idx) = idx {
+                            self.pending_changes.push(GuiChange::Set(idx, download.to_owned()));
+                        }
+                        self.pending_changes.push(GuiChange::Panicked(true,
+                                                                      format!("{}: verification failed: {}",
+                                                                              download.name(),
+                                                                              e)));
+                    }
+                }
+            }
         }
     }
 }
 
+// nanoseconds between gui updates
+fn gui_update_time() -> u64 {
+    config::with(|c| c.gui_update_ms) * 1000000
+}
+
 trait AutoIncrement {
     fn increment(&mut self, key: u64, value: usize);
 }
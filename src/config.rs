@@ -0,0 +1,205 @@
+use std::io::prelude::*;
+use std::fs::{File, create_dir_all};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use rustc_serialize::json;
+use rustc_serialize::json::Json;
+use paths;
+use helper::write_replacing;
+use pathtemplate::{NameCase, SpaceMode, CollisionPolicy};
+use fsthread::LinkMode;
+use sources::DEFAULT_FORMAT;
+use constants::CONFIG_FILE_NAME;
+
+lazy_static! {
+    // set from --config before the config is first read
+    static ref CONFIG_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+    // as loaded at startup, for settings that only take effect on the next launch
+    static ref STARTUP_CONFIG: Config = load();
+    static ref CONFIG: RwLock<Config> = RwLock::new(STARTUP_CONFIG.clone());
+}
+
+// Everything editable from the Preferences dialog, kept as JSON in the config dir
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Config {
+    // None uses $XDG_DOWNLOAD_DIR/pbook-gui, or downloads/ of a portable install
+    pub download_dir: Option<String>,
+    // 0 uses one per cpu
+    pub threads: usize,
+    pub connect_timeout_ms: u64,
+    // times a failed download is queued again before it is reported
+    pub retries: usize,
+    // bytes per second shared by all downloads, 0 for unlimited
+    pub bandwidth_limit: usize,
+    // theme folder or "auto", None reads theme.txt
+    pub theme: Option<String>,
+    // file types taken from the book list (restart)
+    pub accepted_formats: Vec<String>,
+    // where a book goes under the download dir, see pathtemplate.rs
+    pub path_template: String,
+    pub path_case: NameCase,
+    pub path_spaces: SpaceMode,
+    // characters per file name, 0 for no limit
+    pub path_max_length: usize,
+    // filled in for {language}
+    pub list_language: String,
+    // two different books with the same path
+    pub collision_policy: CollisionPolicy,
+    // a book in several categories is downloaded once and linked into the others
+    pub duplicate_link: LinkMode,
+    // queue the downloads that were unfinished when the program was last closed, paused
+    pub restore_selection: bool,
+    // book list to read instead of the built in one, a path or an http(s) url
+    pub list_source: Option<String>,
+    // how list_source is read, an id from sources.rs
+    pub list_format: String,
+    // for awesome lists: the heading level of the top categories, headings that aren't
+    // categories and whether only links in the accepted formats are books
+    pub awesome_heading_level: usize,
+    pub awesome_skip_headings: Vec<String>,
+    pub awesome_formats_only: bool,
+    // categories whose new books are queued whenever the list is read
+    pub subscriptions: Vec<String>,
+    // minutes between rereading list_source, 0 to only read it at startup
+    pub refresh_minutes: u64,
+    pub gui_update_ms: u64,
+    // seconds, see rate.rs
+    pub speed_window: f64,
+    pub speed_smoothing: f64,
+    // download list sort column and direction
+    pub sort_column: Option<i32>,
+    pub sort_descending: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            download_dir: None,
+            threads: 0,
+            connect_timeout_ms: 5000,
+            retries: 0,
+            bandwidth_limit: 0,
+            theme: None,
+            accepted_formats: vec!["pdf".to_owned()],
+            path_template: "{category}/{title}.{ext}".to_owned(),
+            path_case: NameCase::Keep,
+            path_spaces: SpaceMode::Underscore,
+            path_max_length: 0,
+            list_language: "English".to_owned(),
+            collision_policy: CollisionPolicy::Suffix,
+            duplicate_link: LinkMode::Hardlink,
+            restore_selection: true,
+            list_source: None,
+            list_format: DEFAULT_FORMAT.to_owned(),
+            awesome_heading_level: 2,
+            awesome_skip_headings: vec!["Contents".to_owned(),
+                                        "Table of Contents".to_owned(),
+                                        "Contributing".to_owned(),
+                                        "License".to_owned()],
+            awesome_formats_only: true,
+            subscriptions: Vec::new(),
+            refresh_minutes: 0,
+            gui_update_ms: 10,
+            speed_window: 3.0,
+            speed_smoothing: 1.0,
+            sort_column: None,
+            sort_descending: false,
+        }
+    }
+}
+
+// copy of the live config
+pub fn current() -> Config {
+    CONFIG.read().unwrap().clone()
+}
+
+// read a single setting without cloning the whole config
+pub fn with<F, T>(f: F) -> T
+    where F: FnOnce(&Config) -> T
+{
+    f(&CONFIG.read().unwrap())
+}
+
+pub fn startup() -> &'static Config {
+    &STARTUP_CONFIG
+}
+
+// replace the live config and write it out
+pub fn set(config: Config) -> Result<(), String> {
+    *CONFIG.write().unwrap() = config.clone();
+    save(&config)
+}
+
+// change part of the live config and write it out
+pub fn update<F>(f: F) -> Result<(), String>
+    where F: FnOnce(&mut Config)
+{
+    let config = {
+        let mut config = CONFIG.write().unwrap();
+        f(&mut config);
+        config.clone()
+    };
+    save(&config)
+}
+
+// the configured download dir, or the default one
+pub fn download_dir() -> PathBuf {
+    with(|c| c.download_dir.clone())
+        .map(PathBuf::from)
+        .unwrap_or(paths::default_download_dir())
+}
+
+// only has an effect before the config is first used
+pub fn set_config_path(path: PathBuf) {
+    *CONFIG_PATH.write().unwrap() = Some(path);
+}
+
+pub fn config_path() -> PathBuf {
+    match *CONFIG_PATH.read().unwrap() {
+        Some(ref path) => path.clone(),
+        None => paths::config_dir().join(CONFIG_FILE_NAME),
+    }
+}
+
+fn load() -> Config {
+    let path = config_path();
+    let mut contents = String::new();
+    if let Err(e) = File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        info!("No config at {:?} ({}), using defaults", path, e);
+        return Config::default();
+    }
+    match decode_over_defaults(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Invalid config {:?}, using defaults: {}", path, e);
+            Config::default()
+        }
+    }
+}
+
+// settings missing from the file (e.g. added in a later version) keep their defaults
+fn decode_over_defaults(contents: &str) -> Result<Config, String> {
+    let file_json = try!(Json::from_str(contents).map_err(|e| format!("{}", e)));
+    let default_json = try!(Json::from_str(&json::encode(&Config::default()).unwrap())
+                                .map_err(|e| format!("{}", e)));
+    let mut merged = match default_json {
+        Json::Object(object) => object,
+        _ => return Err("Default config is not an object".to_owned()),
+    };
+    match file_json {
+        Json::Object(object) => merged.extend(object),
+        _ => return Err("Config is not a JSON object".to_owned()),
+    }
+    let mut decoder = json::Decoder::new(Json::Object(merged));
+    ::rustc_serialize::Decodable::decode(&mut decoder).map_err(|e| format!("{}", e))
+}
+
+fn save(config: &Config) -> Result<(), String> {
+    let path = config_path();
+    if let Some(parent) = path.parent() {
+        try!(create_dir_all(parent).map_err(|e| format!("Failed to create config dir {:?}: {}", parent, e)));
+    }
+    let encoded = format!("{}", json::as_pretty_json(config));
+    write_replacing(&path, encoded.as_bytes())
+        .map_err(|e| format!("Failed to save config to {:?}: {}", path, e))
+}
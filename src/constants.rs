@@ -1,6 +1,3 @@
-// gui update time for commhandler (ns)
-pub const GUI_UPDATE_TIME: u64 = 10000000;
-
 // theme setup constants
 #[cfg(windows)]
 pub const DEFAULT_GTK_CSS_CONFIG: &'static str = "..\\gtk.css";
@@ -11,12 +8,44 @@ pub const DEFAULT_GTK_CSS_CONFIG: &'static str = "../gtk.css";
 pub const SECONDARY_GTK_CSS_CONFIG: &'static str = "gtk.css";
 
 pub const GTK_THEME_CFG: &'static str = "theme.txt";
+// theme folder used when none is chosen or the chosen one fails to load
+pub const DEFAULT_GTK_THEME: &'static str = "arc";
+// theme choice that follows the desktop's dark/light preference
+pub const AUTO_GTK_THEME: &'static str = "auto";
+// theme folders, next to the install and in the user config/data dirs
+pub const THEMES_DIR_NAME: &'static str = "themes";
+// user stylesheet in the config dir, applied on top of the theme
+pub const USER_GTK_CSS: &'static str = "gtk.css";
+// milliseconds between checks of the stylesheets for changes
+pub const THEME_WATCH_INTERVAL: u32 = 1000;
+
+// download speed history (seconds)
+// one speed history point is kept per interval, up to HISTORY_LEN of them
+pub const DOWNLOAD_SPEED_HISTORY_INTERVAL: f64 = 1.0;
+pub const DOWNLOAD_SPEED_HISTORY_LEN: usize = 30;
+
+// folder inside the user config/data dirs
+pub const APP_DIR_NAME: &'static str = "pbook-gui";
+
+// data dir (relative to the install root) and logging
+pub const DATA_DIR_NAME: &'static str = "data";
+// downloads dir next to the install in portable mode
+pub const DOWNLOADS_DIR_NAME: &'static str = "downloads";
+// file in the install root that turns on portable mode
+pub const PORTABLE_MARKER: &'static str = "portable";
+pub const LOG_DIR_NAME: &'static str = "logs";
+pub const LOG_FILE_NAME: &'static str = "pbook-gui.log";
+// env var holding the log filter, e.g. "debug" or "info,downloader=trace"
+pub const LOG_ENV_VAR: &'static str = "PBOOK_LOG";
+// bytes before the log file is rotated
+pub const LOG_MAX_BYTES: u64 = 1048576;
+// rotated log files to keep (pbook-gui.log.1 ... pbook-gui.log.N)
+pub const LOG_MAX_FILES: usize = 5;
 
-// determines the amount of time to keep recent byte amount before resetting it to 0
-// (recent bytes downloaded in this time)
-// in seconds
-pub const DOWNLOAD_SPEED_UPDATE_TIME: f64 = 0.5;
-pub const DOWNLOAD_SPEED_SMOOTH_FACTOR: f64 = 0.005;
+// between the headings of a nested category's name
+pub const CATEGORY_SEPARATOR: &'static str = " / ";
 
-// milliseconds before giving up on a connection
-pub const CONNECT_MILLI_TIMEMOUT: u64 = 5000;
+// settings file in the user config dir
+pub const CONFIG_FILE_NAME: &'static str = "config.json";
+// the book list and unfinished downloads as of the last run, in the user data dir
+pub const CATALOG_FILE_NAME: &'static str = "catalog.json";
@@ -1,33 +1,63 @@
 use std::hash::{Hash, Hasher, SipHasher};
 pub use std::path::{Path, PathBuf};
-use time::precise_time_s;
-use time;
-use helper::{minimum, maximum, make_string_if_nonzero};
-use constants::{DOWNLOAD_SPEED_UPDATE_TIME, DOWNLOAD_SPEED_SMOOTH_FACTOR};
-use std::i64;
+use std::collections::VecDeque;
+use std::ops::Range;
+use time::{precise_time_s, get_time, Timespec};
+use helper::{minimum, maximum, format_duration, normalize_url};
+use rate::RateEstimator;
+use constants::CATEGORY_SEPARATOR;
+pub use pathtemplate::CollisionPolicy;
 // refactor TpoolProgressMsg to just be a DownloadUpdate
 pub enum DownloadUpdate {
     Message(String),
     Amount(usize),
     SetSize(usize),
+    Response(ResponseInfo),
+    Resumed(usize), // bytes already on disk
     Panicked(String),
     Finished,
+    Exited, // the downloader's thread is done with its files, whatever happened
 }
 
 pub enum GuiCmdMsg {
     Add(u64, PathBuf),
+    AddPaused(u64, PathBuf), // listed but left out of the queue until resumed
     Restart(usize), // usize = index
     Cancel(usize),
     Open(usize),
+    OpenFile(usize),
+    Pause(usize),
+    Resume(usize),
+    MoveToTop(usize),
+    MoveToBottom(usize),
+    Verify(usize),
+    DeleteFile(usize),
+    Batch(BatchAction, Vec<u64>), // applied to every id in turn
+    RetryFailed,
+    ClearFinished,
     Remove(u64),
     ChangeDir(PathBuf),
     SetThreads(usize),
+    ResolveCollision(u64, CollisionPolicy),
+    Extend(Vec<Download>), // books new in a reread list, so they can be added
     Stop,
 }
 
+// actions that can be applied to a multi-row selection
+#[derive(Clone, Copy, Debug)]
+pub enum BatchAction {
+    Cancel,
+    Restart,
+    Pause,
+    Resume,
+    Remove,
+    Open,
+}
+
 #[derive(Clone, Debug)]
 pub enum TpoolCmdMsg {
     Remove(u64),
+    Pause(u64),
     ChangeDir(PathBuf),
     Stop,
 }
@@ -42,7 +72,29 @@ pub enum GuiChange {
     Add(Download), // download
     Set(usize, Download), // idx, download
     Open(String),
+    OpenFile(String),
+    Notice(String),
+    Collision(u64, String), // id, what it collides with, answered with ResolveCollision
     Panicked(bool, String), // id -- work on this
+    Summary(QueueSummary),
+    Extend(Vec<Download>), // books new in a reread list, for the sidebar
+}
+
+// aggregate over every download in the list, sent every gui update
+#[derive(Debug, Clone, Default)]
+pub struct QueueSummary {
+    // bytes per second, over all active downloads
+    pub speed: f32,
+    pub queued: usize,
+    pub active: usize,
+    pub paused: usize,
+    pub done: usize,
+    pub failed: usize,
+    pub downloaded: usize,
+    // sum of the sizes that are known so far
+    pub known_total: usize,
+    // seconds until the whole queue is done, None if it can't be estimated
+    pub eta: Option<f64>,
 }
 
 // pub type GuiUpdateMsg = Vec<(String, Option<u64>, Option<usize>, Option<Download>)>;
@@ -62,16 +114,21 @@ impl ToDownloads for Vec<Category> {
     }
 }
 
+// A heading of the list with the books right under it. Lists keep categories in the
+// order of the headings, so a category's subcategories are the deeper ones that follow it.
 #[derive(Debug, Clone)]
 pub struct Category {
+    // the headings from the top, "Language Agnostic / Algorithms", unique in a list
     name: String,
+    path: Vec<String>,
     downloads: Vec<Download>,
 }
 
 impl Category {
-    pub fn new(name: String, downloads: Vec<Download>) -> Category {
+    pub fn new(path: Vec<String>, downloads: Vec<Download>) -> Category {
         Category {
-            name: name,
+            name: path.join(CATEGORY_SEPARATOR),
+            path: path,
             downloads: downloads,
         }
     }
@@ -82,6 +139,20 @@ impl Category {
         &self.name
     }
 
+    // its own heading
+    pub fn title(&self) -> &str {
+        self.path.last().map(|title| title.as_str()).unwrap_or(&self.name)
+    }
+
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    // 0 for a top level category
+    pub fn depth(&self) -> usize {
+        self.path.len().saturating_sub(1)
+    }
+
     pub fn downloads(&self) -> &[Download] {
         &self.downloads
     }
@@ -90,20 +161,58 @@ impl Category {
         &self.downloads[idx]
     }
 
+    // a heading with only subcategories under it has nothing to enable itself
     pub fn enabled(&self) -> bool {
-        self.downloads.iter().all(|x| x.enabled())
+        !self.downloads.is_empty() && self.downloads.iter().all(|x| x.enabled())
     }
 
     // Setter functions
 
-    pub fn add_download(&mut self, download: Download) {
+    pub fn add_download(&mut self, mut download: Download) {
+        let url = normalize_url(download.url());
+        let occurrence = self.downloads
+                             .iter()
+                             .filter(|d| normalize_url(d.url()) == url)
+                             .count();
+        download.id = stable_id(&self.name, download.url(), occurrence);
+        download.category_name = Some(self.name.clone());
+        download.category_path = self.path.clone();
+        download.index = self.downloads.len() + 1;
         self.downloads.push(download);
     }
+
+    // a book that already got its id in another copy of the list
+    pub fn push_download(&mut self, download: Download) {
+        self.downloads.push(download);
+    }
+}
+
+// a category and its subcategories, as positions in the list
+pub fn subtree(categories: &[Category], idx: usize) -> Range<usize> {
+    let depth = categories[idx].depth();
+    let end = categories[idx + 1..]
+                  .iter()
+                  .position(|category| category.depth() <= depth)
+                  .map(|end| idx + 1 + end)
+                  .unwrap_or(categories.len());
+    idx..end
 }
 
-pub fn get_hash_id(name: &str, url: &str) -> u64 {
+// "Language Agnostic / Algorithms" -> ["Language Agnostic", "Algorithms"]
+pub fn category_path(category_name: &str) -> Vec<String> {
+    category_name.split(CATEGORY_SEPARATOR).map(|title| title.to_owned()).collect()
+}
+
+// Ids are saved between runs, so they can't depend on anything a list edit is likely
+// to touch. The title is left out so fixing a typo keeps the id, and so is the position
+// so reordering does. The category and the number of earlier listings of the url in it
+// tell apart the same url listed more than once.
+pub fn stable_id(category_name: &str, url: &str, occurrence: usize) -> u64 {
     let mut hasher = SipHasher::new();
-    format!("{}{}", name, url).hash(&mut hasher);
+    normalize_url(url).hash(&mut hasher);
+    category_name.trim().to_lowercase().hash(&mut hasher);
+    // usize hashes differently on 32 and 64 bit
+    (occurrence as u64).hash(&mut hasher);
     hasher.finish()
 }
 
@@ -116,18 +225,38 @@ pub struct Download {
     download_info: Option<DownloadInfo>, /* optional depending on whether
                                           * its currently being downloaded */
     category_name: Option<String>,
+    // the category's headings, one folder each under {category}
+    category_path: Vec<String>,
+    author: Option<String>,
+    // position in its category, starting at 1
+    index: usize,
+    // where it goes under the download dir, set from the path template when added
+    relative_path: Option<PathBuf>,
+    // replace a file that's already there instead of keeping it
+    overwrite: bool,
+    // continue a .tmp file left by a pause, anything else starts over
+    resume: bool,
 }
 
 impl Download {
     pub fn new(name: &str, url: &str, category_name: Option<String>) -> Download {
-        // id is siphash of name + url
+        // the id is final once the download is added to its category
+        let id = stable_id(category_name.as_ref().map(|c| c.as_str()).unwrap_or(""), url, 0);
         Download {
-            id: get_hash_id(name, url),
+            id: id,
             name: name.to_owned(),
             url: url.to_owned(),
             enabled: false,
             download_info: None,
+            category_path: category_name.as_ref()
+                                        .map(|name| category_path(name))
+                                        .unwrap_or(Vec::new()),
             category_name: category_name,
+            author: None,
+            index: 0,
+            relative_path: None,
+            overwrite: false,
+            resume: false,
         }
     }
 
@@ -165,14 +294,52 @@ impl Download {
         }
     }
 
+    pub fn state(&self) -> Option<DownloadState> {
+        if let Some(ref download_info) = self.download_info {
+            Some(download_info.state())
+        } else {
+            None
+        }
+    }
+
     pub fn category_name(&self) -> Option<String> {
         self.category_name.clone()
     }
 
+    pub fn category_path(&self) -> &[String] {
+        &self.category_path
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_ref().map(|a| a.as_str())
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    // the download dir it was added with
     pub fn path(&self) -> PathBuf {
         self.clone().download_info.unwrap().get_path()
     }
 
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    pub fn resume(&self) -> bool {
+        self.resume
+    }
+
+    pub fn relative_path(&self) -> PathBuf {
+        self.relative_path.clone().unwrap_or(PathBuf::from(&self.name))
+    }
+
+    // the book itself
+    pub fn file_path(&self) -> PathBuf {
+        self.path().join(self.relative_path())
+    }
+
     // Setter functions
 
     pub fn set_total(&mut self, total: usize) {
@@ -187,6 +354,22 @@ impl Download {
         }
     }
 
+    pub fn set_author(&mut self, author: Option<String>) {
+        self.author = author;
+    }
+
+    pub fn set_relative_path(&mut self, relative_path: PathBuf) {
+        self.relative_path = Some(relative_path);
+    }
+
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    pub fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
+
     pub fn set_enable_state(&mut self, newstate: bool) {
         self.enabled = newstate;
     }
@@ -195,6 +378,15 @@ impl Download {
         self.download_info = Some(DownloadInfo::new());
     }
 
+    // like start_download, but keeps the attempt count and error history
+    pub fn restart_download(&mut self) {
+        let restarted = match self.download_info {
+            Some(ref download_info) => download_info.restarted(),
+            None => DownloadInfo::new(),
+        };
+        self.download_info = Some(restarted);
+    }
+
     pub fn stop_download(&mut self) {
         self.download_info = None;
     }
@@ -216,6 +408,36 @@ impl Download {
         }    
     }
 
+    pub fn set_started(&mut self) {
+        if let Some(ref mut download_info) = self.download_info {
+            download_info.set_started();
+        }
+    }
+
+    pub fn set_failed(&mut self, error: String) {
+        if let Some(ref mut download_info) = self.download_info {
+            download_info.set_failed(error);
+        }
+    }
+
+    pub fn set_response(&mut self, response: ResponseInfo) {
+        if let Some(ref mut download_info) = self.download_info {
+            download_info.set_response(response);
+        }
+    }
+
+    pub fn set_state(&mut self, state: DownloadState) {
+        if let Some(ref mut download_info) = self.download_info {
+            download_info.set_state(state);
+        }
+    }
+
+    pub fn set_progress(&mut self, progress: usize) {
+        if let Some(ref mut download_info) = self.download_info {
+            download_info.set_progress(progress);
+        }
+    }
+
     pub fn progress(&self) -> Option<usize> {
         if let Some(ref download_info) = self.download_info {
             Some(download_info.progress())
@@ -225,34 +447,68 @@ impl Download {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DownloadState {
+    Queued,
+    Downloading,
+    Paused,
+    Finished,
+    Failed,
+}
+
+// what the server answered with, after redirects
+#[derive(Debug, Clone)]
+pub struct ResponseInfo {
+    pub status: String,
+    pub final_url: String,
+    pub content_type: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct DownloadInfo {
-    failed: bool,
+    state: DownloadState,
     progress: usize,
     total: usize,
-    prev_progress: usize,
-    recent_progress: usize,
-    recent_progress_clear_time: f64,
-    finished: bool,
+    rate: RateEstimator,
     start_time: f64, 
+    // wall clock times for display
+    queued_at: Timespec,
+    started_at: Option<Timespec>,
+    finished_at: Option<Timespec>,
+    attempts: usize,
+    errors: Vec<(Timespec, String)>,
+    response: Option<ResponseInfo>,
     path: PathBuf,
 }
 
 impl DownloadInfo {
     pub fn new() -> DownloadInfo {
         DownloadInfo {
-            failed: false,
+            state: DownloadState::Queued,
             progress: 0,
             total: 0,
-            prev_progress: 0,
-            recent_progress: 0,
-            recent_progress_clear_time: precise_time_s() + DOWNLOAD_SPEED_UPDATE_TIME,
-            finished: false,
+            rate: RateEstimator::from_config(precise_time_s()),
             start_time: precise_time_s(),
+            queued_at: get_time(),
+            started_at: None,
+            finished_at: None,
+            attempts: 0,
+            errors: Vec::new(),
+            response: None,
             path: PathBuf::new(),
         }
     }
 
+    // fresh progress for another try, carrying over the history
+    pub fn restarted(&self) -> DownloadInfo {
+        let mut restarted = DownloadInfo::new();
+        restarted.path = self.path.to_path_buf();
+        restarted.attempts = self.attempts;
+        restarted.errors = self.errors.clone();
+        restarted
+    }
+
     // Getters
     
     pub fn progress(&self) -> usize {
@@ -264,7 +520,35 @@ impl DownloadInfo {
     }
 
     pub fn finished(&self) -> bool {
-        self.finished
+        self.state == DownloadState::Finished
+    }
+
+    pub fn state(&self) -> DownloadState {
+        self.state
+    }
+
+    pub fn queued_at(&self) -> Timespec {
+        self.queued_at
+    }
+
+    pub fn started_at(&self) -> Option<Timespec> {
+        self.started_at
+    }
+
+    pub fn finished_at(&self) -> Option<Timespec> {
+        self.finished_at
+    }
+
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    pub fn errors(&self) -> &[(Timespec, String)] {
+        &self.errors
+    }
+
+    pub fn response(&self) -> &Option<ResponseInfo> {
+        &self.response
     }
 
     pub fn get_path(&self) -> PathBuf {
@@ -276,46 +560,55 @@ impl DownloadInfo {
     }
 
     // to bytes per second
-    // http://stackoverflow.com/questions/2779600/how-to-estimate-download-time-remaining-accurately
     pub fn speed(&self) -> f32 {
-        if self.progress >= self.total {
+        if self.progress >= self.total || self.state != DownloadState::Downloading {
             0.0
         } else {
-            let now = precise_time_s();
-            let speed_avg = self.progress as f64 / (now - self.start_time);
-            let speed_recent  = self.prev_progress as f64 / DOWNLOAD_SPEED_UPDATE_TIME;
-            let float_speed = DOWNLOAD_SPEED_SMOOTH_FACTOR * speed_recent + (1.0 - DOWNLOAD_SPEED_SMOOTH_FACTOR) * speed_avg;
-            float_speed as f32
+            self.rate.rate() as f32
         }
     }
 
-    // to seconds
-    pub fn eta(&self) -> String {
-        let bytes_left;
-        if self.total < self.progress {
-            bytes_left = 0;
+    // recent speeds, oldest first
+    pub fn speed_history(&self) -> &VecDeque<f32> {
+        self.rate.history()
+    }
+
+    // seconds left, None if it can't be estimated
+    pub fn eta_seconds(&self) -> Option<f64> {
+        let speed = self.speed();
+        if self.progress >= self.total {
+            Some(0.0)
+        } else if speed == 0.0 {
+            None
         } else {
-            bytes_left = self.total - self.progress;
+            Some((self.total - self.progress) as f64 / speed as f64)
         }
-        let speed = self.speed();
-        let eta = bytes_left as f32 / speed;
-        let streta;
+    }
+
+    pub fn eta(&self) -> String {
         if self.progress == 0 && self.total == 0 {
-            streta = "N/A".to_owned();
+            "N/A".to_owned()
         } else if self.progress >= self.total {
-            streta = "Done.".to_owned();
-        } else if maximum(eta, 1000000.0) == eta || speed == 0.0 {
-            streta = "∞".to_owned();
+            "Done.".to_owned()
+        } else {
+            match self.eta_seconds() {
+                Some(eta) if eta < 1000000.0 => format_duration(eta),
+                _ => "∞".to_owned(),
+            }
+        }
+    }
+
+    // average over the whole download so far
+    pub fn average_speed(&self) -> f32 {
+        let elapsed = match (self.started_at, self.finished_at) {
+            (Some(started), Some(finished)) => (finished - started).num_milliseconds() as f64 / 1000.0,
+            _ => precise_time_s() - self.start_time,
+        };
+        if elapsed > 0.0 {
+            (self.progress as f64 / elapsed) as f32
         } else {
-            let dur = time::Duration::seconds(maximum(minimum(eta as i64, i64::MAX), 0));
-            streta = format!("{}{}{}{}{}s",
-                             make_string_if_nonzero(dur.num_weeks(), "w"),
-                             make_string_if_nonzero(dur.num_days(), "d"),
-                             make_string_if_nonzero(dur.num_hours(), "h"),
-                             make_string_if_nonzero(dur.num_minutes(), "m"),
-                             dur.num_seconds());
+            0.0
         }
-        streta
     }
 
     // Setters
@@ -325,9 +618,41 @@ impl DownloadInfo {
     }
 
     pub fn set_finished(&mut self) {
-        self.recent_progress = 0;
+        self.rate.reset();
         self.progress = self.total;
-        self.finished = true;
+        self.state = DownloadState::Finished;
+        self.finished_at = Some(get_time());
+    }
+
+    pub fn set_failed(&mut self, error: String) {
+        self.state = DownloadState::Failed;
+        self.finished_at = Some(get_time());
+        self.errors.push((get_time(), error));
+    }
+
+    pub fn set_response(&mut self, response: ResponseInfo) {
+        self.response = Some(response);
+    }
+
+    // speed and average are measured from here rather than from when it was queued
+    pub fn set_started(&mut self) {
+        let now = precise_time_s();
+        self.state = DownloadState::Downloading;
+        self.start_time = now;
+        self.rate = RateEstimator::from_config(now);
+        self.started_at = Some(get_time());
+        self.attempts += 1;
+    }
+
+    pub fn set_state(&mut self, state: DownloadState) {
+        if state == DownloadState::Paused {
+            self.rate.reset();
+        }
+        self.state = state;
+    }
+
+    pub fn set_progress(&mut self, progress: usize) {
+        self.progress = progress;
     }
 
     pub fn set_path(&mut self, path: PathBuf) {
@@ -338,17 +663,6 @@ impl DownloadInfo {
 
     pub fn increment_progress(&mut self, increment: usize) {
         self.progress += increment;
-        let timenow = precise_time_s();
-        if timenow >= self.recent_progress_clear_time {
-            self.recent_progress += increment;
-            self.prev_progress = self.recent_progress;
-            self.recent_progress = 0;
-            if self.progress >= self.total {
-                self.prev_progress = 0;
-            }
-            self.recent_progress_clear_time = timenow + DOWNLOAD_SPEED_UPDATE_TIME;
-        } else {
-            self.recent_progress += increment;
-        }
+        self.rate.add_sample(precise_time_s(), increment);
     }
 }
@@ -0,0 +1,181 @@
+use gtk;
+use gtk::prelude::*;
+use gtk::{Orientation, ButtonBoxStyle};
+use data::*;
+use helper::{format_timestamp, ToByteUnits};
+
+const FIELDS: [&'static str; 12] = ["Name",
+                                    "URL",
+                                    "Final URL",
+                                    "Destination",
+                                    "Category",
+                                    "Status",
+                                    "Content type",
+                                    "Attempts",
+                                    "Queued",
+                                    "Started",
+                                    "Finished",
+                                    "Average speed"];
+
+// Everything known about the selected download
+#[derive(Clone)]
+pub struct DetailsPane {
+    pub frame: gtk::Frame,
+    values: Vec<gtk::Label>,
+    headers: gtk::TextView,
+    errors: gtk::TextView,
+    pub copy_url_button: gtk::Button,
+    pub open_file_button: gtk::Button,
+    pub open_folder_button: gtk::Button,
+    pub retry_button: gtk::Button,
+}
+
+impl DetailsPane {
+    pub fn new() -> DetailsPane {
+        let frame = gtk::Frame::new(Some("Details"));
+        let grid = gtk::Grid::new();
+        grid.set_column_spacing(10);
+        grid.set_row_spacing(2);
+        let mut values = Vec::with_capacity(FIELDS.len());
+        for (row, field) in FIELDS.iter().enumerate() {
+            let name_label = gtk::Label::new(Some(field));
+            name_label.set_halign(gtk::Align::End);
+            let value_label = gtk::Label::new(None);
+            value_label.set_halign(gtk::Align::Start);
+            value_label.set_selectable(true);
+            value_label.set_line_wrap(true);
+            grid.attach(&name_label, 0, row as i32, 1, 1);
+            grid.attach(&value_label, 1, row as i32, 1, 1);
+            values.push(value_label);
+        }
+
+        let headers = make_text_view();
+        let errors = make_text_view();
+        let text_box = gtk::Box::new(Orientation::Horizontal, 10);
+        text_box.pack_start(&wrap_in_frame("Response headers", &headers), true, true, 0);
+        text_box.pack_start(&wrap_in_frame("Errors", &errors), true, true, 0);
+
+        let copy_url_button = gtk::Button::new_with_label("Copy URL");
+        let open_file_button = gtk::Button::new_with_label("Open File");
+        let open_folder_button = gtk::Button::new_with_label("Open Folder");
+        let retry_button = gtk::Button::new_with_label("Retry");
+        let button_box = gtk::ButtonBox::new(Orientation::Horizontal);
+        button_box.set_layout(ButtonBoxStyle::Start);
+        button_box.add(&copy_url_button);
+        button_box.add(&open_file_button);
+        button_box.add(&open_folder_button);
+        button_box.add(&retry_button);
+
+        let holder = gtk::Box::new(Orientation::Vertical, 5);
+        holder.set_border_width(5);
+        holder.pack_start(&grid, false, false, 0);
+        holder.pack_start(&text_box, true, true, 0);
+        holder.pack_start(&button_box, false, false, 0);
+        frame.add(&holder);
+
+        let pane = DetailsPane {
+            frame: frame,
+            values: values,
+            headers: headers,
+            errors: errors,
+            copy_url_button: copy_url_button,
+            open_file_button: open_file_button,
+            open_folder_button: open_folder_button,
+            retry_button: retry_button,
+        };
+        pane.clear();
+        pane
+    }
+
+    pub fn show(&self, download: &Download) {
+        let mut values = vec![download.name().to_owned(),
+                              download.url().to_owned(),
+                              String::new(),
+                              String::new(),
+                              download.category_name().unwrap_or(String::new()),
+                              String::new(),
+                              String::new(),
+                              String::new(),
+                              String::new(),
+                              String::new(),
+                              String::new(),
+                              String::new()];
+        let mut headers = String::new();
+        let mut errors = String::new();
+        if let &Some(ref info) = download.download_info() {
+            values[3] = format!("{}", download.file_path().display());
+            values[5] = format!("{:?}", info.state());
+            if let &Some(ref response) = info.response() {
+                values[2] = response.final_url.clone();
+                values[5] = format!("{} ({})", values[5], response.status);
+                values[6] = response.content_type.clone().unwrap_or(String::new());
+                headers = response.headers
+                                  .iter()
+                                  .map(|&(ref name, ref value)| format!("{}: {}", name, value))
+                                  .collect::<Vec<String>>()
+                                  .join("\n");
+            }
+            values[7] = info.attempts().to_string();
+            values[8] = format_timestamp(info.queued_at());
+            values[9] = info.started_at().map(format_timestamp).unwrap_or(String::new());
+            values[10] = info.finished_at().map(format_timestamp).unwrap_or(String::new());
+            values[11] = format!("{}/s", info.average_speed().convert_to_byte_units(1));
+            errors = info.errors()
+                         .iter()
+                         .map(|&(time, ref error)| format!("{}  {}", format_timestamp(time), error))
+                         .collect::<Vec<String>>()
+                         .join("\n");
+
+            let state = info.state();
+            self.open_file_button.set_sensitive(state == DownloadState::Finished);
+            self.open_folder_button.set_sensitive(true);
+            self.retry_button.set_sensitive(state == DownloadState::Finished ||
+                                            state == DownloadState::Failed);
+        } else {
+            self.open_file_button.set_sensitive(false);
+            self.open_folder_button.set_sensitive(false);
+            self.retry_button.set_sensitive(false);
+        }
+        for (label, value) in self.values.iter().zip(values.iter()) {
+            label.set_text(value);
+        }
+        set_text_view(&self.headers, &headers);
+        set_text_view(&self.errors, &errors);
+        self.copy_url_button.set_sensitive(true);
+    }
+
+    pub fn clear(&self) {
+        for label in self.values.iter() {
+            label.set_text("");
+        }
+        set_text_view(&self.headers, "");
+        set_text_view(&self.errors, "");
+        self.copy_url_button.set_sensitive(false);
+        self.open_file_button.set_sensitive(false);
+        self.open_folder_button.set_sensitive(false);
+        self.retry_button.set_sensitive(false);
+    }
+}
+
+fn make_text_view() -> gtk::TextView {
+    let text_view = gtk::TextView::new();
+    text_view.set_editable(false);
+    text_view.set_cursor_visible(false);
+    text_view
+}
+
+fn wrap_in_frame(title: &str, text_view: &gtk::TextView) -> gtk::Frame {
+    let scroll = gtk::ScrolledWindow::new(None, None);
+    scroll.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+    scroll.set_size_request(-1, 80);
+    scroll.add(text_view);
+    let frame = gtk::Frame::new(Some(title));
+    frame.add(&scroll);
+    frame
+}
+
+fn set_text_view(text_view: &gtk::TextView, text: &str) {
+    if let Some(buffer) = text_view.get_buffer() {
+        buffer.set_text(text);
+    }
+}
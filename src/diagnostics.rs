@@ -0,0 +1,147 @@
+use rustc_serialize::json;
+use data::Category;
+
+// why the parser left a line of the list out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, RustcEncodable)]
+pub enum DiagnosticKind {
+    // a list item that isn't a "[title](url)" link
+    MalformedLink,
+    // a link that isn't in one of the accepted formats
+    FilteredFormat,
+    // kept, with a number after the title
+    DuplicateTitle,
+    // a heading without any books under it
+    EmptyCategory,
+    // a list that can't be read at all, e.g. invalid json
+    InvalidList,
+}
+
+impl DiagnosticKind {
+    pub fn label(&self) -> &'static str {
+        match *self {
+            DiagnosticKind::MalformedLink => "Malformed link",
+            DiagnosticKind::FilteredFormat => "Format not accepted",
+            DiagnosticKind::DuplicateTitle => "Duplicate title",
+            DiagnosticKind::EmptyCategory => "Empty category",
+            DiagnosticKind::InvalidList => "Invalid list",
+        }
+    }
+}
+
+#[derive(Debug, Clone, RustcEncodable)]
+pub struct Diagnostic {
+    // counted from 1 like an editor does, the book's position in a json list
+    pub line: usize,
+    pub kind: DiagnosticKind,
+    pub category: String,
+    // the line as it is in the list
+    pub text: String,
+}
+
+// Collects what the parser skipped over, headings skipped on purpose (like "Index") are
+// left out
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics { entries: Vec::new() }
+    }
+
+    pub fn push(&mut self, line: usize, kind: DiagnosticKind, category: &str, text: &str) {
+        self.entries.push(Diagnostic {
+            line: line,
+            kind: kind,
+            category: category.to_owned(),
+            text: text.to_owned(),
+        });
+    }
+
+    // in the order of the list
+    pub fn sort(&mut self) {
+        self.entries.sort_by(|a, b| (a.line, a.kind).cmp(&(b.line, b.kind)));
+    }
+
+    pub fn entries(&self) -> &[Diagnostic] {
+        &self.entries
+    }
+
+    pub fn count(&self, kind: DiagnosticKind) -> usize {
+        self.entries.iter().filter(|entry| entry.kind == kind).count()
+    }
+}
+
+// "2 malformed links, 140 format not accepted"
+pub fn summarize(diagnostics: &Diagnostics) -> String {
+    let kinds = [DiagnosticKind::MalformedLink,
+                 DiagnosticKind::FilteredFormat,
+                 DiagnosticKind::DuplicateTitle,
+                 DiagnosticKind::EmptyCategory,
+                 DiagnosticKind::InvalidList];
+    let counts = kinds.iter()
+                      .map(|&kind| (kind, diagnostics.count(kind)))
+                      .filter(|&(_, count)| count > 0)
+                      .map(|(kind, count)| format!("{} {}", count, kind.label().to_lowercase()))
+                      .collect::<Vec<String>>();
+    if counts.is_empty() {
+        "Nothing was skipped".to_owned()
+    } else {
+        counts.join(", ")
+    }
+}
+
+#[derive(RustcEncodable)]
+struct LintReport {
+    source: String,
+    categories: usize,
+    books: usize,
+    skipped: Vec<Diagnostic>,
+}
+
+// --lint, prints what the parser skipped and returns the exit code. Links in a format that
+// isn't downloaded are expected in most lists, anything else a maintainer can fix makes it 1.
+pub fn lint(source: &str,
+            categories: &[Category],
+            diagnostics: &Diagnostics,
+            json_output: bool)
+            -> i32 {
+    let books = categories.iter().fold(0, |books, category| books + category.downloads().len());
+    if json_output {
+        let report = LintReport {
+            source: source.to_owned(),
+            categories: categories.len(),
+            books: books,
+            skipped: diagnostics.entries().to_vec(),
+        };
+        match json::encode(&report) {
+            Ok(encoded) => println!("{}", encoded),
+            Err(e) => {
+                println!("Failed to encode the lint report: {}", e);
+                return 1;
+            }
+        }
+    } else {
+        for entry in diagnostics.entries() {
+            println!("{}:{}: {} in {}: {}",
+                     source,
+                     entry.line,
+                     entry.kind.label(),
+                     entry.category,
+                     entry.text);
+        }
+        println!("{} categories, {} books, {}",
+                 categories.len(),
+                 books,
+                 summarize(diagnostics).to_lowercase());
+    }
+    let problems = diagnostics.entries()
+                              .iter()
+                              .any(|entry| entry.kind != DiagnosticKind::FilteredFormat);
+    if problems {
+        1
+    } else {
+        0
+    }
+}
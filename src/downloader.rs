@@ -1,21 +1,36 @@
 use std::sync::mpsc::{Sender, Receiver};
 use std::io::prelude::*;
 use std::io::{Error, BufWriter, ErrorKind};
-use std::fs::{File, copy, create_dir_all, rename, metadata, remove_file};
+use std::fs::{File, OpenOptions, copy, create_dir_all, rename, metadata, remove_file};
 use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use hyper;
 use hyper::client::Client;
 use hyper::client::response::Response;
-use hyper::header::ContentLength;
+use hyper::header::{ContentLength, ContentType, Range, ByteRangeSpec};
+use hyper::status::StatusCode;
 use data::*;
-use constants::CONNECT_MILLI_TIMEMOUT;
-use helper::{name_to_fname, name_to_dname, Ignore};
+use config;
+use helper::{tmp_path, maximum, Ignore};
+use sanitize::contained_path;
 use std::thread::sleep;
+use time::precise_time_s;
+
+// downloaders currently alive, the bandwidth limit is split between them
+static ACTIVE_DOWNLOADERS: AtomicUsize = ATOMIC_USIZE_INIT;
+// seconds the bandwidth limit is averaged over before it is read again
+const THROTTLE_PERIOD: f64 = 1.0;
 
 pub struct Downloader {
     url: String,
     id: u64,
-    category_name: Option<String>,
+    // the download dir and the path under it, kept when the dir changes
+    root: PathBuf,
+    relative_path: PathBuf,
+    // replace whatever is at actualpath rather than skipping
+    overwrite: bool,
+    // continue the .tmp file of a paused download
+    resume: bool,
     cmd_recv: Receiver<TpoolCmdMsg>,
     progress_send: Sender<TpoolProgressMsg>,
     actualpath: PathBuf,
@@ -24,6 +39,15 @@ pub struct Downloader {
     stream: Option<Response>,
     outfile: Option<BufWriter<File>>,
     buffer: [u8; 128],
+    // for the lifecycle log
+    start_time: f64,
+    bytes: usize,
+    // size of a .tmp file left by an earlier pause
+    resume_from: u64,
+    // bytes/s this downloader may use, 0 for unlimited
+    throttle_limit: f64,
+    throttle_start: f64,
+    throttle_bytes: usize,
 }
 
 impl Downloader {
@@ -31,41 +55,53 @@ impl Downloader {
                cmd_recv: Receiver<TpoolCmdMsg>,
                progress_send: Sender<TpoolProgressMsg>)
         -> Downloader {
-            let dlname = download.name().to_owned();
-            let path = download.path().to_owned().join(name_to_fname(&dlname));
+            let path = download.file_path();
+            let timeout = config::with(|c| c.connect_timeout_ms);
+            ACTIVE_DOWNLOADERS.fetch_add(1, Ordering::SeqCst);
             Downloader {
                 url: download.url().to_owned(),
                 id: download.id(),
-                category_name: download.category_name().to_owned(),
+                root: download.path(),
+                relative_path: download.relative_path(),
+                overwrite: download.overwrite(),
+                resume: download.resume(),
                 cmd_recv: cmd_recv,
                 progress_send: progress_send,
                 actualpath: path.clone(),
-                filepath: path.parent()
-                    .unwrap()
-                    .join(path.file_name()
-                          .unwrap()
-                          .to_str()
-                          .unwrap()
-                          .to_owned() + ".tmp"),
+                filepath: tmp_path(&path),
                           client: {
                               let mut client = Client::new();
-                              client.set_read_timeout(Some(Duration::from_millis(CONNECT_MILLI_TIMEMOUT)));
+                              client.set_read_timeout(Some(Duration::from_millis(timeout)));
                               client
                           },
                           stream: None,
                           outfile: None,
                           buffer: [0; 128],
+                          start_time: precise_time_s(),
+                          bytes: 0,
+                          resume_from: 0,
+                          throttle_limit: 0.0,
+                          throttle_start: 0.0,
+                          throttle_bytes: 0,
             }
         }
 
     pub fn begin(&mut self) -> Result<(), String> {
+        // checked again right before anything is written
+        try!(contained_path(&self.root, &self.relative_path));
+        if self.overwrite && File::open(&self.actualpath).is_ok() {
+            info!("Overwriting {:?} with {}", self.actualpath, self.url);
+            try!(remove_file(&self.actualpath)
+                     .map_err(|e| format!("Failed to remove {:?}: {}", self.actualpath, e)));
+        }
         let actual_exists;
         let filepath_exists;
         {
             actual_exists = File::open(&self.actualpath).is_ok();
-            filepath_exists = File::open(&self.actualpath).is_ok();
+            filepath_exists = File::open(&self.filepath).is_ok();
         }
         if actual_exists {
+            info!("Skipping {}: {:?} already exists", self.url, self.actualpath);
             // get file metadata (size)
             let filelength;
             if let Ok(metadata) = metadata(self.actualpath.clone()) {
@@ -83,19 +119,58 @@ impl Downloader {
                 .expect("Failed to send finished");
             return Err("finished".to_owned());
         } else {
-            if filepath_exists {
-                // remove the preexisting tmp file
-                remove_file(self.filepath.as_path()).expect(&format!("Failed to remove pre-existing .tmp file: {:?}", self.filepath));
+            if filepath_exists && self.resume {
+                // try to pick up the preexisting tmp file where it left off
+                self.resume_from = metadata(&self.filepath).map(|m| m.len()).unwrap_or(0);
+            } else if filepath_exists {
+                // left by a failed or restarted try, its bytes can't be trusted
+                info!("Discarding {:?}", self.filepath);
+                try!(remove_file(&self.filepath)
+                         .map_err(|e| format!("Failed to remove {:?}: {}", self.filepath, e)));
             }
+            info!("GET {} -> {:?}", self.url, self.actualpath);
+            self.start_time = precise_time_s();
             if let Err(e) = self.get_url(0, 5) {
                 return Err(e);
             }
 
+            let mut resumed = false;
             if let Some(ref stream) = self.stream {
+                info!("{} {} (final url {})", stream.status, self.url, stream.url);
+                debug!("Response headers for {}:\n{}", self.url, stream.headers);
+                let response = ResponseInfo {
+                    status: format!("{}", stream.status),
+                    final_url: format!("{}", stream.url),
+                    content_type: stream.headers.get::<ContentType>().map(|c| format!("{}", c)),
+                    headers: stream.headers
+                                   .iter()
+                                   .map(|h| (h.name().to_owned(), h.value_string()))
+                                   .collect(),
+                };
+                self.progress_send
+                    .send((self.id, DownloadUpdate::Response(response)))
+                    .ignore();
+                // servers that ignore the range send the whole file again
+                resumed = self.resume_from > 0 && stream.status == StatusCode::PartialContent;
+                let offset = if resumed {
+                    self.resume_from
+                } else {
+                    0
+                };
+                if self.resume_from > 0 {
+                    if resumed {
+                        info!("Resuming {} at {} bytes", self.url, offset);
+                    } else {
+                        info!("{} can't be resumed, starting over", self.url);
+                    }
+                    self.progress_send
+                        .send((self.id, DownloadUpdate::Resumed(offset as usize)))
+                        .ignore();
+                }
                 match stream.headers.get::<ContentLength>() {
                     Some(content_length) => {
                         self.progress_send
-                            .send((self.id, DownloadUpdate::SetSize(**content_length as usize)))
+                            .send((self.id, DownloadUpdate::SetSize((offset + **content_length) as usize)))
                             .expect("Failed to send content length");
                     }
                     None => {}
@@ -108,16 +183,21 @@ impl Downloader {
                 if let Err(e) = create_dir_all(&self.filepath
                                                .parent()
                                                .expect("No such dir parent")) {
-                    println!("dir creation error");
+                    error!("Failed to create dir for {:?}: {}", self.filepath, e);
                     return Err(format!("{}", e));
                 }
 
-                match File::create(&self.filepath) {
+                let file = if resumed {
+                    OpenOptions::new().append(true).open(&self.filepath)
+                } else {
+                    File::create(&self.filepath)
+                };
+                match file {
                     Ok(f) => {
                         self.outfile = Some(BufWriter::new(f));
                     }
                     Err(e) => {
-                        println!("fopen error");
+                        error!("Failed to create {:?}: {}", self.filepath, e);
                         return Err(format!("{}", e));
                     }
                 }
@@ -131,7 +211,14 @@ impl Downloader {
             return Err(format!("Over connection try limit of {}.", maxtries));
         } else {
             if let None = self.stream {
-                match self.client.get(&self.url).send() {
+                let result = {
+                    let mut request = self.client.get(&self.url);
+                    if self.resume_from > 0 {
+                        request = request.header(Range::Bytes(vec![ByteRangeSpec::AllFrom(self.resume_from)]));
+                    }
+                    request.send()
+                };
+                match result {
                     Ok(s) => {
                         self.stream = Some(s);
                         return Ok(());
@@ -141,6 +228,7 @@ impl Downloader {
                             if ioerr.kind() == ErrorKind::WouldBlock {
                                 return self.get_url(tries, maxtries);
                             } else {
+                                debug!("Connection attempt {} for {} failed: {}", tries + 1, self.url, ioerr);
                                 return self.get_url(tries + 1, maxtries);
                             }
                         } else {
@@ -161,13 +249,24 @@ impl Downloader {
             match cmd {
                 TpoolCmdMsg::Remove(id) => {
                     if self.id == id {
+                        self.log_stopped();
                         return Err("stopped".to_owned());
                     }
                 }
+                TpoolCmdMsg::Pause(id) => {
+                    if self.id == id {
+                        if let Some(ref mut outfile) = self.outfile {
+                            outfile.flush().ignore();
+                        }
+                        info!("Paused {} after {} bytes", self.url, self.bytes);
+                        return Err("paused".to_owned());
+                    }
+                }
                 TpoolCmdMsg::ChangeDir(newdir) => {
                     self.change_path_dir(&newdir);
                 }
                 TpoolCmdMsg::Stop => {
+                    self.log_stopped();
                     return Err("stopped".to_owned());
                 }
             }
@@ -180,10 +279,16 @@ impl Downloader {
                         // Finished downloading
                         outfile.flush().expect("Failed to flush to outfile");
                         drop(outfile);
-                        rename(&self.filepath, &self.actualpath).ignore();
-                            //.expect(&format!("Failed to rename tmp file: {:?} to {:?}",
-                                             //self.filepath,
-                                             //self.actualpath));
+                        if let Err(e) = rename(&self.filepath, &self.actualpath) {
+                            warn!("Failed to rename tmp file {:?} to {:?}: {}",
+                                  self.filepath,
+                                  self.actualpath,
+                                  e);
+                        }
+                        info!("Finished {}: {} bytes in {:.1}s",
+                              self.url,
+                              self.bytes,
+                              precise_time_s() - self.start_time);
                         self.progress_send
                             .send((self.id, DownloadUpdate::Finished))
                             //.expect("Failed to send message");
@@ -193,10 +298,15 @@ impl Downloader {
                     Ok(n) => {
                         // got n bytes
                         outfile.write(&self.buffer[..n]).expect("IO write error");
+                        self.bytes += n;
                         self.progress_send
                             .send((self.id, DownloadUpdate::Amount(n)))
                             //.expect("Failed to send message");
                             .ignore();
+                        throttle(&mut self.throttle_limit,
+                                 &mut self.throttle_start,
+                                 &mut self.throttle_bytes,
+                                 n);
                     }
                     Err(e) => {
                         // Some error
@@ -227,16 +337,16 @@ impl Downloader {
     }
 
     fn change_path_dir(&mut self, newdir: &Path) {
-        let current_filename = self.filepath.file_name().unwrap().to_owned();
-        let newpath;
-        if let Some(ref category) = self.category_name {
-            let category_dir = newdir.join(name_to_dname(category));
-            create_dir_all(&category_dir).expect("Failed to create dir");
-            newpath = category_dir.join(current_filename);
-        } else {
-            newpath = newdir.join(current_filename);
+        let newpath = match contained_path(newdir, &self.relative_path) {
+            Ok(newpath) => newpath,
+            Err(e) => return self.send_message(e),
+        };
+        self.root = newdir.to_path_buf();
+        if let Some(parent) = newpath.parent() {
+            create_dir_all(parent).expect("Failed to create dir");
         }
-        self.change_path(&newpath);
+        self.actualpath = newpath.clone();
+        self.change_path(&tmp_path(&newpath));
     }
 
     fn change_path(&mut self, newpath: &Path) {
@@ -311,7 +421,19 @@ impl Downloader {
         }
     }
 
+    fn log_stopped(&self) {
+        info!("Stopped {} after {} bytes in {:.1}s",
+              self.url,
+              self.bytes,
+              precise_time_s() - self.start_time);
+    }
+
     pub fn send_panicked(&self, e: String) {
+        error!("Failed {} after {} bytes in {:.1}s: {}",
+               self.url,
+               self.bytes,
+               precise_time_s() - self.start_time,
+               e);
         self.progress_send.send((self.id, DownloadUpdate::Panicked(e))).ignore();
     }
 }
@@ -321,3 +443,30 @@ fn make_chdir_error(errorstring: Error, kind: &str) -> String {
             kind,
             errorstring)
 }
+
+impl Drop for Downloader {
+    fn drop(&mut self) {
+        ACTIVE_DOWNLOADERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Sleeps whenever the bytes read this period got ahead of this downloader's share of
+// the bandwidth limit. The share is worked out again every period so it follows both
+// the config and the number of running downloads.
+fn throttle(limit: &mut f64, period_start: &mut f64, period_bytes: &mut usize, n: usize) {
+    let now = precise_time_s();
+    if now - *period_start >= THROTTLE_PERIOD {
+        let total_limit = config::with(|c| c.bandwidth_limit) as f64;
+        let active = maximum(ACTIVE_DOWNLOADERS.load(Ordering::SeqCst), 1) as f64;
+        *limit = total_limit / active;
+        *period_start = now;
+        *period_bytes = 0;
+    }
+    *period_bytes += n;
+    if *limit > 0.0 {
+        let ahead = *period_bytes as f64 / *limit - (now - *period_start);
+        if ahead > 0.0 {
+            sleep(Duration::from_millis((ahead * 1000.0) as u64));
+        }
+    }
+}
@@ -1,18 +1,39 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
 use std::thread;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use helper::Ignore;
 use std::time::Duration;
 
+// how a book listed in several categories shows up in all but the first
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum LinkMode {
+    Hardlink,
+    Symlink,
+    Copy,
+}
+
+// (label, value) for the preferences dialog
+pub const LINK_MODES: [(&'static str, LinkMode); 3] = [("Hard links", LinkMode::Hardlink),
+                                                       ("Symbolic links", LinkMode::Symlink),
+                                                       ("Copies", LinkMode::Copy)];
+
 pub enum FsCommand {
     Copy(PathBuf, PathBuf), // source, dest
+    Link(u64, PathBuf, PathBuf, LinkMode, bool), // dest download's id, source, dest, overwrite
     Remove(PathBuf),
+    Verify(u64, PathBuf, usize), // id, path, expected size (0 if unknown)
     Stop,
 }
 
 pub enum FsUpdate {
     Error(String),
+    Verified(u64, Result<usize, String>), // id, size on disk or what's wrong
+    Linked(u64, Result<(), String>),
+    Taken(u64), // a link's dest already holds a different file
 }
 
 pub struct FsThread {
@@ -71,6 +92,18 @@ impl FsThread {
                                 }
                             }
                         }
+                        FsCommand::Link(id, source, dest, mode, overwrite) => {
+                            let update = match link_file(&source, &dest, mode, overwrite) {
+                                Ok(false) => FsUpdate::Taken(id),
+                                result => FsUpdate::Linked(id, result.map(|_| ())),
+                            };
+                            fsthread.update_send.send(update).ignore();
+                        }
+                        FsCommand::Verify(id, path, expected) => {
+                            fsthread.update_send
+                                    .send(FsUpdate::Verified(id, verify_file(&path, expected)))
+                                    .ignore();
+                        }
                         FsCommand::Stop => {
                             run = false;
                         }
@@ -83,3 +116,116 @@ impl FsThread {
         return (command_send, update_recv);
     }
 }
+
+// puts the downloaded file at a second path, copying where links aren't possible,
+// Ok(false) if a different file is there already and isn't to be overwritten
+fn link_file(source: &Path, dest: &Path, mode: LinkMode, overwrite: bool) -> Result<bool, String> {
+    if fs::symlink_metadata(dest).is_ok() {
+        if already_linked(source, dest) {
+            // from an earlier run
+            return Ok(true);
+        }
+        if !overwrite {
+            return Ok(false);
+        }
+        info!("Overwriting {:?} with {:?}", dest, source);
+        try!(fs::remove_file(dest).map_err(|e| format!("{:?}: {}", dest, e)));
+    }
+    if let Some(parent) = dest.parent() {
+        try!(fs::create_dir_all(parent).map_err(|e| format!("{:?}: {}", parent, e)));
+    }
+    let linked = match mode {
+        LinkMode::Hardlink => fs::hard_link(source, dest),
+        LinkMode::Symlink => symlink(source, dest),
+        LinkMode::Copy => fs::copy(source, dest).map(|_| ()),
+    };
+    match linked {
+        Ok(_) => Ok(true),
+        // e.g. a hard link across drives, or symlinks without permission on windows
+        Err(e) if mode != LinkMode::Copy => {
+            warn!("{:?} link to {:?} failed ({}), copying instead", mode, dest, e);
+            fs::copy(source, dest).map(|_| true).map_err(|e| format!("{:?}: {}", dest, e))
+        }
+        Err(e) => Err(format!("{:?}: {}", dest, e)),
+    }
+}
+
+// a symlink to the source, a hard link of it or a copy with the same contents
+fn already_linked(source: &Path, dest: &Path) -> bool {
+    if fs::read_link(dest).is_ok() {
+        return match (fs::canonicalize(source), fs::canonicalize(dest)) {
+            (Ok(source), Ok(target)) => source == target,
+            _ => false,
+        };
+    }
+    same_inode(source, dest) || same_contents(source, dest).unwrap_or(false)
+}
+
+// a different book of the same size isn't a copy, so the bytes are compared
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    if try!(fs::metadata(a)).len() != try!(fs::metadata(b)).len() {
+        return Ok(false);
+    }
+    let (mut a, mut b) = (try!(File::open(a)), try!(File::open(b)));
+    let (mut a_buf, mut b_buf) = ([0u8; 8192], [0u8; 8192]);
+    loop {
+        let read = try!(a.read(&mut a_buf));
+        if read == 0 {
+            return Ok(true);
+        }
+        try!(b.read_exact(&mut b_buf[..read]));
+        if a_buf[..read] != b_buf[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn same_inode(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// no stable file ids, comparing the contents catches hard links there
+#[cfg(windows)]
+fn same_inode(_: &Path, _: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, dest: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(source, dest)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, dest: &Path) -> io::Result<()> {
+    ::std::os::windows::fs::symlink_file(source, dest)
+}
+
+// checks that the file is all there and looks like what it claims to be
+fn verify_file(path: &Path, expected: usize) -> Result<usize, String> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len() as usize,
+        Err(e) => return Err(format!("{:?}: {}", path, e)),
+    };
+    if expected > 0 && size != expected {
+        return Err(format!("expected {} bytes, found {}", expected, size));
+    }
+    let is_pdf = path.extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| e.to_lowercase() == "pdf")
+                    .unwrap_or(false);
+    if is_pdf {
+        let mut magic = [0u8; 5];
+        let read = File::open(path).and_then(|mut f| f.read(&mut magic));
+        match read {
+            Ok(n) if n == magic.len() && &magic == b"%PDF-" => {}
+            Ok(_) => return Err("not a PDF file (missing %PDF- header)".to_owned()),
+            Err(e) => return Err(format!("{:?}: {}", path, e)),
+        }
+    }
+    Ok(size)
+}
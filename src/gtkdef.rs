@@ -1,13 +1,16 @@
 use glib;
 use gtk_sys;
 use gdk;
-use gobject_sys::g_object_set;
+use gdk_sys;
+use gobject_sys::{g_object_set, g_object_get, GObject, GParamSpec};
 use pango_sys::PangoEllipsizeMode;
-use libc::{ssize_t, c_void, c_char};
+use libc::{ssize_t, c_void, c_char, c_int};
 use gtk_sys::{GtkStyleProvider, GtkCssProvider};
 use gtk::{CssProvider, StyleContext, is_initialized, CellRendererText, ToValue};
-use glib::translate::{ToGlibPtr, Stash};
+use glib::translate::{ToGlibPtr, Stash, from_glib_full};
+use glib::signal::connect;
 use std::ffi::CString;
+use std::mem::transmute;
 
 pub trait SetEllipsizeMode {
     fn set_ellipsize_mode(&self, mode: PangoEllipsizeMode);
@@ -79,3 +82,94 @@ impl AddCssProvider for StyleContext {
         }
     }
 }
+
+pub trait RemoveCssProvider {
+    fn remove_provider_for_screen(screen: &gdk::Screen, provider: &CssProvider);
+}
+
+impl RemoveCssProvider for StyleContext {
+    fn remove_provider_for_screen(screen: &gdk::Screen, provider: &CssProvider) {
+        if !is_initialized() {
+            panic!("Gtk not initialized");
+        }
+        unsafe {
+            let provider_pointer: *mut GtkCssProvider = provider.to_glib_none().0;
+            let cast_provider_pointer = provider_pointer as *mut GtkStyleProvider;
+            gtk_sys::gtk_style_context_remove_provider_for_screen(screen.to_glib_none().0,
+                                                                  cast_provider_pointer)
+        }
+    }
+}
+
+pub fn copy_to_clipboard(text: &str) {
+    if !is_initialized() {
+        panic!("Gtk not initialized");
+    }
+    let selection = CString::new("CLIPBOARD").unwrap();
+    let text = CString::new(text.replace("\0", "")).unwrap();
+    unsafe {
+        let atom = gdk_sys::gdk_atom_intern(selection.as_ptr(), 0);
+        let clipboard = gtk_sys::gtk_clipboard_get(atom);
+        gtk_sys::gtk_clipboard_set_text(clipboard, text.as_ptr(), -1);
+    }
+}
+
+// boolean property of the default GtkSettings, e.g. "gtk-application-prefer-dark-theme"
+pub fn get_settings_bool(property: &str) -> bool {
+    if !is_initialized() {
+        panic!("Gtk not initialized");
+    }
+    let property = CString::new(property).unwrap();
+    let nullptr: *const c_void = ::std::ptr::null();
+    let mut value: c_int = 0;
+    unsafe {
+        let settings = gtk_sys::gtk_settings_get_default();
+        if settings.is_null() {
+            return false;
+        }
+        g_object_get(settings as *mut c_void, property.as_ptr(), &mut value, nullptr);
+    }
+    value != 0
+}
+
+// string property of the default GtkSettings, e.g. "gtk-theme-name"
+pub fn get_settings_string(property: &str) -> Option<String> {
+    if !is_initialized() {
+        panic!("Gtk not initialized");
+    }
+    let property = CString::new(property).unwrap();
+    let nullptr: *const c_void = ::std::ptr::null();
+    let mut value: *mut c_char = ::std::ptr::null_mut();
+    unsafe {
+        let settings = gtk_sys::gtk_settings_get_default();
+        if settings.is_null() {
+            return None;
+        }
+        g_object_get(settings as *mut c_void, property.as_ptr(), &mut value, nullptr);
+        from_glib_full(value)
+    }
+}
+
+// calls f whenever a property of the default GtkSettings changes, e.g. "gtk-theme-name"
+pub fn connect_settings_notify<F: Fn() + 'static>(property: &str, f: F) {
+    if !is_initialized() {
+        panic!("Gtk not initialized");
+    }
+    unsafe {
+        let settings = gtk_sys::gtk_settings_get_default();
+        if settings.is_null() {
+            return;
+        }
+        let f: Box<Box<Fn() + 'static>> = Box::new(Box::new(f));
+        connect(settings as *mut GObject,
+                &format!("notify::{}", property),
+                transmute(settings_notify_trampoline as usize),
+                Box::into_raw(f) as *mut _);
+    }
+}
+
+unsafe extern "C" fn settings_notify_trampoline(_: *mut GObject,
+                                                _: *mut GParamSpec,
+                                                f: &Box<Fn() + 'static>) {
+    f();
+}
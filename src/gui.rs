@@ -3,11 +3,11 @@ use gtk;
 use gtk::prelude::*;
 use gtk::{Orientation, ButtonBoxStyle};
 use gio_sys::g_app_info_launch_default_for_uri;
-use std::env;
 use std::sync::mpsc::{Sender, Receiver, SendError};
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
-use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::fs;
 use std::ptr::null_mut;
 use std::ffi::CString;
@@ -16,36 +16,48 @@ use glib::types::Type;
 use helper::*;
 use cellrenderers::*;
 use theme::*;
-use constants::{DEFAULT_GTK_CSS_CONFIG, SECONDARY_GTK_CSS_CONFIG};
+use constants::{DEFAULT_GTK_CSS_CONFIG, SECONDARY_GTK_CSS_CONFIG, AUTO_GTK_THEME,
+                CATEGORY_SEPARATOR};
 use include::RAW_ICON;
 use gdk_pixbuf::PixbufLoader;
 use button::*;
 use menu::*;
+use details::DetailsPane;
+use gtkdef::copy_to_clipboard;
+use paths;
+use config;
+use preferences::run_preferences;
+use logger;
+use catalog::{self, Catalog, CatalogChange, ChangeKind};
+use changes::run_changes;
+use diagnostics::Diagnostics;
+use skipped::run_skipped;
+use refresh::{self, Refresher, spawn_refresher, subscribed};
+use std::thread;
+use log::LogLevelFilter;
+use args::{Args, category_matches};
+use pathtemplate::COLLISION_POLICIES;
 
 pub fn gui(data: &mut Vec<Category>,
            update_recv_channel: Receiver<GuiUpdateMsg>,
            command_send_channel: Sender<GuiCmdMsg>,
-           threads: usize) {
+           threads: usize,
+           args: &Args,
+           catalog: Catalog,
+           diagnostics: Diagnostics) {
     if gtk::init().is_err() {
         panic!("Failed to initialize GTK.");
     }
 
-    let current_exe_path;
-    match env::current_exe() {
-        Ok(exe_path) => current_exe_path = exe_path.clone(),
-        Err(e) => {
-            println!("Failed to get current exe path: {}", e);
-            current_exe_path = Path::new("./pbook-gui").to_path_buf()
-        }
-    };
-    let current_working_dir = current_exe_path.parent()
-        .unwrap_or(Path::new(".."));
+    let current_exe_dir = paths::exe_dir();
+    let current_working_dir = current_exe_dir.as_path();
     let default_config_path = current_working_dir.join(DEFAULT_GTK_CSS_CONFIG);
     let secondary_config_path = current_working_dir.join(SECONDARY_GTK_CSS_CONFIG);
 
     setup_theme(current_working_dir,
-                default_config_path,
-                secondary_config_path);
+                default_config_path.clone(),
+                secondary_config_path.clone(),
+                args.theme.as_ref().map(|theme| theme.as_str()));
 
     let window = gtk::Window::new(gtk::WindowType::Toplevel);
 
@@ -59,6 +71,19 @@ pub fn gui(data: &mut Vec<Category>,
     let window_icon = pixbuf_loader.get_pixbuf().unwrap();
     window.set_icon(Some(&window_icon));
 
+    watch_theme_files(current_working_dir.to_path_buf(),
+                      default_config_path.clone(),
+                      secondary_config_path.clone());
+    watch_desktop_theme(current_working_dir.to_path_buf(),
+                        default_config_path.clone(),
+                        secondary_config_path.clone());
+
+    let menu_bar = gtk::MenuBar::new();
+    menu_bar.append(&make_themes_menu(&window,
+                                      current_working_dir.to_path_buf(),
+                                      default_config_path.clone(),
+                                      secondary_config_path.clone()));
+
     *DOWNLOADS.lock().unwrap() = Vec::new();
     let initial_model = make_liststore_model(&*DOWNLOADS.lock().unwrap());
     *ID_DOWNLOAD_HM.lock().unwrap() = {
@@ -70,23 +95,73 @@ pub fn gui(data: &mut Vec<Category>,
     };
     // main rendering
     let downloadview = gtk::TreeView::new();
-    // name, size, progress, speed, eta
-    let download_column_types = [Type::String, Type::String, Type::F32, Type::String, Type::String];
+    // name, size, progress, speed, eta, speed history, then the hidden
+    // id, raw size, raw speed, raw eta and visible under the quick filter
+    let download_column_types = [Type::String, Type::String, Type::F32, Type::String, Type::String,
+                                 Type::String, Type::U64, Type::U64, Type::F32, Type::F64, Type::Bool];
     let download_store = gtk::ListStore::new(&download_column_types);
     downloadview.add_text_renderer_column("Name", true, true, false, AddMode::PackStart, true, 0);
     downloadview.add_text_renderer_column("Size", true, true, false, AddMode::PackStart, false, 1);
     downloadview.add_progress_renderer_column("Progress", true, true, true, AddMode::PackStart, 2);
     downloadview.add_text_renderer_column("Speed", true, true, false, AddMode::PackStart, false, 3);
     downloadview.add_text_renderer_column("ETA", true, true, false, AddMode::PackStart, true, 4);
+    downloadview.add_text_renderer_column("History", true, true, false, AddMode::PackStart, false, 5);
+    // sort by the raw values rather than the displayed text
+    let sort_columns = [(0, 0), (1, COL_RAW_SIZE), (2, 2), (3, COL_RAW_SPEED), (4, COL_RAW_ETA)];
+    for &(view_column, sort_column) in sort_columns.iter() {
+        if let Some(column) = downloadview.get_column(view_column) {
+            column.set_sort_column_id(sort_column);
+        }
+    }
 
     for item in initial_model {
         download_store.add_download(item.1);
     }
 
-    downloadview.set_model(Some(&download_store));
+    // store -> quick filter -> sort, paths from the view have to be converted back
+    let download_filter = gtk::TreeModelFilter::new(&download_store, None);
+    download_filter.set_visible_column(COL_VISIBLE);
+    let download_sort = gtk::TreeModelSort::new(&download_filter);
+
+    // grouped mode, the same columns plus the category a row belongs to,
+    // the category rows themselves hold the totals of their downloads
+    let mut group_column_types = download_column_types.to_vec();
+    group_column_types.push(Type::String);
+    let group_store = gtk::TreeStore::new(&group_column_types);
+    let group_filter = gtk::TreeModelFilter::new(&group_store, None);
+    group_filter.set_visible_column(COL_VISIBLE);
+    let group_sort = gtk::TreeModelSort::new(&group_filter);
+
+    for sort_model in [&download_sort, &group_sort].iter() {
+        if let Some((sort_column, order)) = load_sort_order() {
+            sort_model.set_sort_column_id(sort_column, order);
+        }
+        sort_model.connect_sort_column_changed(|sortable| {
+            if let Some((sort_column, order)) = sortable.get_sort_column_id() {
+                if let Err(e) = save_sort_order(sort_column, order) {
+                    warn!("{}", e);
+                }
+            }
+        });
+    }
+
+    downloadview.set_model(Some(&download_sort));
     downloadview.set_headers_visible(true);
 
     // add right click context menu for downloads
+    downloadview.get_selection().set_mode(gtk::SelectionMode::Multiple);
+    downloadview.connect_button_press_event(|ref treeview, ref ebutton| {
+        if is_right_press(*ebutton) {
+            let (x, y) = ebutton.get_position();
+            if let Some((Some(path), _, _, _)) = treeview.get_path_at_pos(x as i32, y as i32) {
+                // keep a multi-row selection intact when right clicking inside it
+                if treeview.get_selection().path_is_selected(&path) {
+                    return Inhibit(true);
+                }
+            }
+        }
+        Inhibit(false)
+    });
     {
         let command_send_channel = command_send_channel.clone();
         downloadview.connect_button_release_event(move |ref treeview, ref ebutton| {
@@ -96,53 +171,141 @@ pub fn gui(data: &mut Vec<Category>,
                 if let Some((Some(path), Some(col), _, _)) = treeview.get_path_at_pos(x as i32,
                                                                                       y as i32) {
                     treeview.grab_focus();
-                    treeview.set_cursor(&path, Some(&col), false);
-
-                    let right_click_menu = gtk::Menu::new();
-                    let menu_buttons = ["Restart", "Open Directory", "Cancel"];
-                    for name in menu_buttons.iter() {
-                        let item = gtk::MenuItem::new_with_label(name);
-                        item.set_name(name);
-                        right_click_menu.append(&item);
+                    let selection = treeview.get_selection();
+                    // right clicking outside the selection starts a new one, like a left click
+                    if !selection.path_is_selected(&path) {
+                        treeview.set_cursor(&path, Some(&col), false);
                     }
-                    // null item workaround
-                    let null_item = gtk::MenuItem::new_with_label("");
-                    null_item.set_name("None");
-                    right_click_menu.add(&null_item);
-                    right_click_menu.set_active(menu_buttons.len() as u32);
-                    right_click_menu.show_all();
-                    null_item.hide();
-                    right_click_menu.popup(3, time);
-                    let command_send_channel = command_send_channel.clone();
-                    right_click_menu.connect_hide(move |this| {
-                        if let Some(selection) = this.get_active() {
-                            //get the index of the item
-                            let idx = path.get_indices()[0] as usize;
-                            match &selection.get_name().unwrap() as &str {
-                                "Restart" => {
-                                    command_send_channel.send(GuiCmdMsg::Restart(idx)).ignore();
-                                }
-                                "Open Directory" => {
-                                    command_send_channel.send(GuiCmdMsg::Open(idx)).ignore();
-                                }
-                                "Cancel" => {
-                                    command_send_channel.send(GuiCmdMsg::Cancel(idx)).ignore();
-                                }
-                                _ => {}
-                            }
+                    let selected = selected_indices(&selection);
+                    if selected.len() > 1 {
+                        popup_batch_menu(&selected, time, command_send_channel.clone());
+                    } else {
+                        if let Some(idx) = store_index(&path) {
+                            popup_download_menu(idx, time, command_send_channel.clone());
                         }
-                        this.destroy();
-                    });
+                    }
                 }
             }
             Inhibit(false)
         });
     }
 
-    // Setup TLS
-    GTK_GLOBAL.with(move |gtk_global| {
-        *gtk_global.borrow_mut() = Some((download_store, update_recv_channel));
-    });
+    // one click actions for the whole list
+    let list_button_box = gtk::ButtonBox::new(Orientation::Horizontal);
+    list_button_box.set_layout(ButtonBoxStyle::Start);
+    let retry_failed_button = gtk::Button::new_with_label("Retry All Failed");
+    let clear_finished_button = gtk::Button::new_with_label("Clear Finished");
+    list_button_box.add(&retry_failed_button);
+    list_button_box.add(&clear_finished_button);
+    let filter_combo = gtk::ComboBoxText::new();
+    for &(name, _) in STATE_FILTERS.iter() {
+        filter_combo.append_text(name);
+    }
+    filter_combo.set_active(0);
+    let list_tools_box = gtk::Box::new(Orientation::Horizontal, 5);
+    list_tools_box.pack_start(&list_button_box, true, true, 0);
+    list_tools_box.pack_end(&filter_combo, false, false, 0);
+    list_tools_box.pack_end(&gtk::Label::new(Some("Show")), false, false, 0);
+    {
+        let download_store = download_store.clone();
+        let group_store = group_store.clone();
+        filter_combo.connect_changed(move |combo| {
+            let active = combo.get_active();
+            if active >= 0 && (active as usize) < STATE_FILTERS.len() {
+                *DOWNLOAD_FILTER.lock().unwrap() = STATE_FILTERS[active as usize].1;
+                refilter_downloads(&download_store, &group_store);
+            }
+        });
+    }
+    let group_check = gtk::CheckButton::new_with_label("Group by category");
+    list_tools_box.pack_end(&group_check, false, false, 0);
+    {
+        let downloadview = downloadview.clone();
+        let download_sort = download_sort.clone();
+        let group_store = group_store.clone();
+        let group_sort = group_sort.clone();
+        group_check.connect_toggled(move |check| {
+            // set first, switching models fires the selection handler
+            let grouped = check.get_active();
+            *GROUP_BY_CATEGORY.lock().unwrap() = grouped;
+            if grouped {
+                sync_grouped_downloads(&group_store);
+                downloadview.set_model(Some(&group_sort));
+                downloadview.expand_all();
+            } else {
+                downloadview.set_model(Some(&download_sort));
+            }
+        });
+    }
+    {
+        let command_send_channel = command_send_channel.clone();
+        retry_failed_button.connect_clicked(move |_| {
+            command_send_channel.send(GuiCmdMsg::RetryFailed).ignore();
+        });
+    }
+    {
+        let command_send_channel = command_send_channel.clone();
+        clear_finished_button.connect_clicked(move |_| {
+            command_send_channel.send(GuiCmdMsg::ClearFinished).ignore();
+        });
+    }
+
+    // details of the selected download
+    let details = DetailsPane::new();
+    {
+        let details = details.clone();
+        downloadview.get_selection().connect_changed(move |selection| {
+            match selected_index(selection) {
+                Some(idx) => {
+                    if let Some(download) = DOWNLOADS.lock().unwrap().get(idx) {
+                        details.show(download);
+                    }
+                }
+                None => details.clear(),
+            }
+        });
+    }
+    {
+        let downloadview = downloadview.clone();
+        details.copy_url_button.connect_clicked(move |_| {
+            if let Some(idx) = selected_index(&downloadview.get_selection()) {
+                if let Some(download) = DOWNLOADS.lock().unwrap().get(idx) {
+                    copy_to_clipboard(download.url());
+                }
+            }
+        });
+    }
+    {
+        let downloadview = downloadview.clone();
+        let command_send_channel = command_send_channel.clone();
+        details.open_file_button.connect_clicked(move |_| {
+            if let Some(idx) = selected_index(&downloadview.get_selection()) {
+                command_send_channel.send(GuiCmdMsg::OpenFile(idx)).ignore();
+            }
+        });
+    }
+    {
+        let downloadview = downloadview.clone();
+        let command_send_channel = command_send_channel.clone();
+        details.open_folder_button.connect_clicked(move |_| {
+            if let Some(idx) = selected_index(&downloadview.get_selection()) {
+                command_send_channel.send(GuiCmdMsg::Open(idx)).ignore();
+            }
+        });
+    }
+    {
+        let downloadview = downloadview.clone();
+        let command_send_channel = command_send_channel.clone();
+        details.retry_button.connect_clicked(move |_| {
+            if let Some(idx) = selected_index(&downloadview.get_selection()) {
+                command_send_channel.send(GuiCmdMsg::Restart(idx)).ignore();
+            }
+        });
+    }
+
+    // aggregate throughput, counts and eta for the whole queue
+    let statusbar = gtk::Statusbar::new();
+    let status_context = statusbar.get_context_id("queue summary");
 
     // add the scroll
     let download_scroll = gtk::ScrolledWindow::new(None, None);
@@ -151,12 +314,20 @@ pub fn gui(data: &mut Vec<Category>,
 
     // put the scroll and downloads together
     let download_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    download_box.pack_start(&list_tools_box, false, false, 0);
     download_box.pack_start(&download_scroll, true, true, 0);
+    download_box.pack_end(&details.frame, false, false, 0);
 
     let categoryview = gtk::TreeView::new();
-    let category_column_types = [Type::String, Type::Bool];
+    // name, enabled, visible under the current search, search text,
+    // then progress, done/total and whether to show them (category rows with downloads),
+    // then the full category name and the book's position in it (-1 on category rows)
+    let category_column_types = [Type::String, Type::Bool, Type::Bool, Type::String,
+                                 Type::F32, Type::String, Type::Bool, Type::String, Type::I32];
     let category_store = gtk::TreeStore::new(&category_column_types);
     category_store.add_categories(&data);
+    let category_filter = gtk::TreeModelFilter::new(&category_store, None);
+    category_filter.set_visible_column(2);
     categoryview.add_text_renderer_column("Categories",
                                           true,
                                           true,
@@ -164,94 +335,112 @@ pub fn gui(data: &mut Vec<Category>,
                                           AddMode::PackStart,
                                           true,
                                           0);
+    categoryview.add_progress_text_renderer_column("Progress",
+                                                   true,
+                                                   true,
+                                                   false,
+                                                   AddMode::PackStart,
+                                                   4,
+                                                   5,
+                                                   6);
     let toggle_cell = categoryview.add_toggle_renderer_column("Enabled?",
                                                               false,
                                                               false,
                                                               false,
                                                               AddMode::PackEnd,
                                                               1);
-    categoryview.set_model(Some(&category_store));
+    categoryview.set_model(Some(&category_filter));
     // make default download directory
 
-    // NOTE: account for whether in bin dir or not
-    let download_dir: PathBuf;
-    if current_working_dir.file_name().unwrap() == "bin" {
-        let cwdparent = current_working_dir.parent().unwrap();
-        download_dir = cwdparent.join("downloads");
-    } else {
-        download_dir = current_working_dir.join("downloads");
-    }
+    // --dir only applies to this session
+    let download_dir = args.dir.clone().unwrap_or(config::download_dir());
 
     if !download_dir.is_dir() {
-        fs::create_dir(download_dir.clone()).expect("Failed to create default download directory");
+        fs::create_dir_all(download_dir.clone()).expect("Failed to create default download directory");
     }
 
     let download_dir_ref: Arc<Mutex<PathBuf>> = Arc::new(Mutex::new(download_dir));
 
+    // refreshes add books to the list while the window is up
+    let categories = Rc::new(RefCell::new(data.to_owned()));
+    let url_siblings = Rc::new(RefCell::new(url_siblings(data)));
+
     // on toggle
     {
-        let data = data.to_owned();
+        let categories = categories.clone();
+        let url_siblings = url_siblings.clone();
         let command_send_channel = command_send_channel.clone();
         let download_dir_ref = download_dir_ref.clone();
         let category_store = category_store.clone();
-        toggle_cell.connect_toggled(move |_, path| {
-            // First send message, then update visually - more informative
-            let indices = path.get_indices();
-            let ref category = data[indices[0] as usize];
+        let category_filter = category_filter.clone();
+        toggle_cell.connect_toggled(move |_, filter_path| {
+            let data = categories.borrow();
+            // the view shows the filtered model, the rows know their place in the data
+            let path = category_filter.convert_path_to_child_path(&filter_path)
+                                      .expect("Invalid filtered TreePath");
+            let main_iter = category_store.get_iter(&path).expect("Invalid TreePath");
+            let category_name = row_category_name(&main_iter, &category_store);
+            let category_idx = match data.iter().position(|c| c.name() == category_name) {
+                Some(category_idx) => category_idx,
+                None => return,
+            };
             let download_dir: PathBuf = (*download_dir_ref.lock().unwrap()).to_path_buf();
-            let category_dir = download_dir.join(name_to_dname(category.name()));
-            // Update data and the view
-            let is_category;
-            match path.get_depth() {
-                1 => {
-                    let category = category.to_owned();
-                    for download in category.downloads().iter() {
-                        // NOTE: PLACEHOLDER PATHS
-                        if let Err(error) = update_download(command_send_channel.clone(),
-                        download.to_owned(),
-                        category_dir.to_path_buf()) {
-                            println!("{}", error);
+            let checked = !get_bool_iter(&main_iter, &category_store, 1);
+            // (category, book, row) of every book that changes, a category takes the
+            // books of its subcategories along
+            let mut toggled = Vec::new();
+            match row_book_idx(&main_iter, &category_store) {
+                Some(download_idx) => toggled.push((category_idx, download_idx, main_iter)),
+                None => {
+                    for idx in subtree(&data, category_idx) {
+                        if let Some(iter) = category_iter(&category_store, data[idx].name()) {
+                            for download_idx in 0..data[idx].downloads().len() {
+                                if let Some(book) = category_store.iter_nth_child(Some(&iter),
+                                                                          download_idx as i32) {
+                                    if get_bool_iter(&book, &category_store, 1) != checked {
+                                        toggled.push((idx, download_idx, book));
+                                    }
+                                }
+                            }
                         }
                     }
-                    is_category = true;
-                }
-                2 => {
-                    let download = category.get_download_at_idx(indices[1] as usize);
-                    if let Err(error) = update_download(command_send_channel.clone(),
-                    download.to_owned(),
-                    category_dir.to_path_buf()) {
-                        println!("{}", error);
-                    }
-                    is_category = false;
-                }
-                _ => {
-                    is_category = false;
                 }
             }
-            let main_iter = category_store.get_iter(&path).expect("Invalid TreePath");
-            toggle_bool_iter(&main_iter, &category_store);
-
-            // set all of a category
-            if is_category {
-                if category_store.iter_has_child(&main_iter) {
-                    let mut child_iter = category_store.iter_children(Some(&main_iter))
-                        .unwrap();
-                    let max_child = category_store.iter_n_children(Some(&main_iter));
-                    for _ in 0..max_child {
-                        toggle_bool_iter(&child_iter, &category_store);
-                        category_store.iter_next(&mut child_iter);
-                    }
+            // First send message, then update visually - more informative
+            for &(idx, download_idx, ref book) in toggled.iter() {
+                let download = data[idx].get_download_at_idx(download_idx);
+                if let Err(error) = update_download(command_send_channel.clone(),
+                                                    download.to_owned(),
+                                                    download_dir.clone()) {
+                    error!("{}", error);
                 }
+                category_store.set_value(book, 1, &checked.to_value());
             }
+            update_category_checks(&category_store);
+
+            // the same book in other categories follows along
+            let toggled = toggled.iter()
+                                 .map(|&(idx, download_idx, _)| (idx, download_idx))
+                                 .collect::<Vec<(usize, usize)>>();
+            sync_url_siblings(&data,
+                              &url_siblings.borrow(),
+                              &toggled,
+                              &category_store,
+                              &command_send_channel,
+                              &download_dir);
         });
     }
 
     let float_threads = threads as f64;
+    let configured_threads = args.threads.unwrap_or(configured_threads(threads)) as f64;
     let thread_box_frame = gtk::Frame::new(None);
     let thread_box = gtk::Box::new(Orientation::Vertical, 0);
     let thread_label = gtk::Label::new(Some("Max threads to use"));
-    let thread_set_scale = gtk::Scale::new_with_range(Orientation::Horizontal, 1.0, float_threads * 4.0, 1.0);
-    thread_set_scale.get_adjustment().set_value(float_threads);
+    let thread_set_scale = gtk::Scale::new_with_range(Orientation::Horizontal,
+                                                      1.0,
+                                                      maximum(float_threads * 4.0, configured_threads),
+                                                      1.0);
+    thread_set_scale.get_adjustment().set_value(configured_threads);
     thread_set_scale.add_mark(float_threads, gtk::PositionType::Bottom, Some("Default"));
     thread_set_scale.add_mark(float_threads, gtk::PositionType::Top, Some(&threads.to_string()));
     thread_set_scale.add_mark(1.0, gtk::PositionType::Top, Some("1"));
@@ -264,7 +453,14 @@ pub fn gui(data: &mut Vec<Category>,
     {
         let command_send_channel = command_send_channel.clone();
         thread_set_scale.get_adjustment().connect_value_changed(move |adj| {
-            command_send_channel.send(GuiCmdMsg::SetThreads(adj.get_value() as usize)).ignore();
+            let new_threads = adj.get_value() as usize;
+            command_send_channel.send(GuiCmdMsg::SetThreads(new_threads)).ignore();
+            // keeps "one per cpu" in the config when that's what the scale was set to
+            if new_threads != configured_threads(threads) {
+                if let Err(e) = config::update(|c| c.threads = new_threads) {
+                    warn!("{}", e);
+                }
+            }
         });
     }
 
@@ -275,11 +471,32 @@ pub fn gui(data: &mut Vec<Category>,
     button_state_box.add(&enable_all_button);
     button_state_box.add(&disable_all_button);
 
+    let verbose_check = gtk::CheckButton::new_with_label("Verbose logging");
+    verbose_check.set_active(logger::level() >= LogLevelFilter::Debug);
+    {
+        // the same as --verbose, back to the defaults or --quiet when it's turned off
+        let quiet = args.quiet;
+        verbose_check.connect_toggled(move |check| {
+            if check.get_active() {
+                logger::set_level(maximum(logger::level(), LogLevelFilter::Debug));
+                logger::set_console_level(LogLevelFilter::Debug);
+            } else {
+                logger::set_level(LogLevelFilter::Info);
+                logger::set_console_level(if quiet {
+                    LogLevelFilter::Warn
+                } else {
+                    LogLevelFilter::Info
+                });
+            }
+        });
+    }
+
     let change_dir_button = gtk::Button::new_with_label("Change Directory");
     let button_holder_box = gtk::Box::new(Orientation::Vertical, 0);
     button_holder_box.add(&change_dir_button);
     button_holder_box.add(&button_state_box);
     button_holder_box.add(&thread_box_frame);
+    button_holder_box.add(&verbose_check);
 
     // change download directory
     {
@@ -308,6 +525,11 @@ pub fn gui(data: &mut Vec<Category>,
                         // Can write to the directory, change the directory to this one
                         let mut current_dir = download_dir_ref.lock().unwrap();
                         *current_dir = file_dir.clone();
+                        if let Err(e) = config::update(|c| {
+                            c.download_dir = Some(file_dir.to_string_lossy().into_owned())
+                        }) {
+                            warn!("{}", e);
+                        }
                         command_send_channel.send(GuiCmdMsg::ChangeDir(file_dir)).expect("Failed to send message");
                     } else {
                         // Cannot write to the directory, tell the user.
@@ -320,37 +542,34 @@ pub fn gui(data: &mut Vec<Category>,
     // connect signals
     {
         let command_send_channel = command_send_channel.clone();
-        let data = data.to_owned();
+        let categories = categories.clone();
         let download_dir_ref = download_dir_ref.clone();
         let category_store = category_store.clone();
         enable_all_button.connect_clicked(move |_| {
+            let data = categories.borrow();
             let download_dir_deref: PathBuf = (*download_dir_ref.lock().unwrap()).to_path_buf();
             for category in data.iter() {
-                let category_dir = download_dir_deref.join(name_to_dname(category.name()));
                 let downloads = category.downloads();
                 for download in downloads {
                     if let Err(e) = command_send_channel.send(GuiCmdMsg::Add(download.id(),
-                    category_dir.clone())) {
+                    download_dir_deref.clone())) {
                         panic!(e);
                     }
                 }
             }
-            // get_iter_first -> get_iter_next until returned false
-            if let Some(first_iter) = category_store.get_iter_first() {
-                let mut iter = first_iter;
-                category_store.set_value(&iter, 1, &true.to_value());
-                while category_store.iter_next(&mut iter) {
-                    category_store.set_value(&iter, 1, &true.to_value());
-                }
-            }
+            for_each_row(&category_store, None, &mut |iter| {
+                category_store.set_value(iter, 1, &true.to_value());
+            });
+            update_category_checks(&category_store);
         });
     }
 
     {
         let command_send_channel = command_send_channel.clone();
-        let data = data.to_owned();
+        let categories = categories.clone();
         let category_store = category_store.clone();
         disable_all_button.connect_clicked(move |_| {
+            let data = categories.borrow();
             for category in data.iter() {
                 let downloads = category.downloads();
                 for download in downloads {
@@ -359,14 +578,72 @@ pub fn gui(data: &mut Vec<Category>,
                     }
                 }
             }
-            // get_iter_first -> get_iter_next until returned false
-            if let Some(first_iter) = category_store.get_iter_first() {
-                let mut iter = first_iter;
-                category_store.set_value(&iter, 1, &false.to_value());
-                while category_store.iter_next(&mut iter) {
-                    category_store.set_value(&iter, 1, &false.to_value());
+            for_each_row(&category_store, None, &mut |iter| {
+                category_store.set_value(iter, 1, &false.to_value());
+            });
+        });
+    }
+
+    // search by title, author, url host or category
+    let search_entry = gtk::Entry::new();
+    search_entry.set_placeholder_text("Search title, author, host or category");
+    let enable_matches_button = gtk::Button::new_with_label("Enable All Matches");
+    let search_box = gtk::Box::new(Orientation::Horizontal, 5);
+    search_box.pack_start(&search_entry, true, true, 0);
+    search_box.pack_start(&enable_matches_button, false, false, 0);
+    {
+        let category_store = category_store.clone();
+        let category_filter = category_filter.clone();
+        let categoryview = categoryview.clone();
+        search_entry.connect_changed(move |entry| {
+            let query = entry.get_text().unwrap_or(String::new()).trim().to_lowercase();
+            filter_categories(&category_store, &query);
+            category_filter.refilter();
+            if query.is_empty() {
+                categoryview.collapse_all();
+            } else {
+                categoryview.expand_all();
+            }
+        });
+    }
+    {
+        let command_send_channel = command_send_channel.clone();
+        let categories = categories.clone();
+        let url_siblings = url_siblings.clone();
+        let download_dir_ref = download_dir_ref.clone();
+        let category_store = category_store.clone();
+        enable_matches_button.connect_clicked(move |_| {
+            let data = categories.borrow();
+            let download_dir: PathBuf = (*download_dir_ref.lock().unwrap()).to_path_buf();
+            let mut toggled = Vec::new();
+            for (category_idx, category) in data.iter().enumerate() {
+                let category_iter = match category_iter(&category_store, category.name()) {
+                    Some(category_iter) => category_iter,
+                    None => continue,
+                };
+                for (download_idx, download) in category.downloads().iter().enumerate() {
+                    if let Some(book) = category_store.iter_nth_child(Some(&category_iter),
+                                                                      download_idx as i32) {
+                        if !get_bool_iter(&book, &category_store, 1) &&
+                           get_bool_iter(&book, &category_store, 2) {
+                            command_send_channel.send(GuiCmdMsg::Add(download.id(),
+                                                                     download_dir.clone()))
+                                                .ignore();
+                            category_store.set_value(&book, 1, &true.to_value());
+                            toggled.push((category_idx, download_idx));
+                        }
+                    }
                 }
             }
+            update_category_checks(&category_store);
+
+            // listings of the same book outside the matches follow along
+            sync_url_siblings(&data,
+                              &url_siblings.borrow(),
+                              &toggled,
+                              &category_store,
+                              &command_send_channel,
+                              &download_dir);
         });
     }
 
@@ -376,6 +653,7 @@ pub fn gui(data: &mut Vec<Category>,
 
     let category_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
     category_box.pack_start(&button_holder_box, false, false, 10);
+    category_box.pack_start(&search_box, false, false, 0);
     category_box.pack_end(&category_scroll, true, true, 0);
 
     // holds both the category list and the info list
@@ -383,47 +661,937 @@ pub fn gui(data: &mut Vec<Category>,
     let lists_holder = gtk::Paned::new(Orientation::Horizontal);
     lists_holder.add1(&category_box);
     lists_holder.add2(&download_box);
-    window.add(&lists_holder);
+
+    // settings
+    let edit_item = gtk::MenuItem::new_with_label("Edit");
+    let edit_menu = gtk::Menu::new();
+    let preferences_item = gtk::MenuItem::new_with_label("Preferences");
+    edit_menu.append(&preferences_item);
+    edit_item.set_submenu(Some(&edit_menu));
+    menu_bar.append(&edit_item);
+
+    // what changed in the book list since the last run
+    let catalog = Rc::new(catalog);
+    let changes = Rc::new(RefCell::new(catalog.changes(data)));
+    let new_ids = changes.borrow()
+                         .iter()
+                         .filter(|change| change.kind == ChangeKind::Added)
+                         .map(|change| change.id)
+                         .collect::<HashSet<u64>>();
+    let list_item = gtk::MenuItem::new_with_label("List");
+    let list_menu = gtk::Menu::new();
+    let changes_item = gtk::MenuItem::new_with_label(&format!("Changes Since Last Run ({})",
+                                                              changes.borrow().len()));
+    list_menu.append(&changes_item);
+    let skipped_item = gtk::MenuItem::new_with_label(&format!("Skipped Entries ({})",
+                                                              diagnostics.entries().len()));
+    list_menu.append(&skipped_item);
+    let refresh_item = gtk::MenuItem::new_with_label("Refresh Now");
+    list_menu.append(&refresh_item);
+    list_item.set_submenu(Some(&list_menu));
+    menu_bar.append(&list_item);
+    match refresh::list_source(args) {
+        Some(source) => {
+            let refresher = Arc::new(Mutex::new(Refresher::new(source, data, args)));
+            spawn_refresher(refresher.clone(),
+                            command_send_channel.clone(),
+                            download_dir_ref.clone());
+            let download_dir_ref = download_dir_ref.clone();
+            let command_send_channel = command_send_channel.clone();
+            refresh_item.connect_activate(move |_| {
+                // reading the list can take a while, the new books show up once it's done
+                let refresher = refresher.clone();
+                let command_send_channel = command_send_channel.clone();
+                let download_dir = download_dir_ref.lock().unwrap().to_path_buf();
+                thread::spawn(move || {
+                    if let Err(e) = refresher.lock()
+                                             .unwrap()
+                                             .refresh(&command_send_channel, &download_dir) {
+                        warn!("{}", e);
+                    }
+                });
+            });
+        }
+        None => {
+            refresh_item.set_sensitive(false);
+            refresh_item.set_tooltip_text(Some("The built in list doesn't change, set a list \
+                                                source in the preferences"));
+        }
+    }
+    {
+        let window = window.clone();
+        let categories = categories.clone();
+        let changes = changes.clone();
+        let previous_categories = catalog.previous_categories();
+        let category_store = category_store.clone();
+        let download_dir_ref = download_dir_ref.clone();
+        let command_send_channel = command_send_channel.clone();
+        changes_item.connect_activate(move |_| {
+            let download_dir = download_dir_ref.lock().unwrap().to_path_buf();
+            // a refresh can come in while the dialog is up
+            let changes = changes.borrow().clone();
+            run_changes(&window, &changes, &download_dir, |new_ids| {
+                let data = categories.borrow();
+                let mut enabled_categories = checked_categories(&data, &category_store);
+                enabled_categories.extend(previous_categories.iter().cloned());
+                enable_downloads(&data,
+                                 |category, download| {
+                                     new_ids.contains(&download.id()) &&
+                                     enabled_categories.iter().any(|name| name == category.name())
+                                 },
+                                 true,
+                                 &category_store,
+                                 &download_dir,
+                                 &command_send_channel)
+            });
+        });
+    }
+    {
+        let window = window.clone();
+        skipped_item.connect_activate(move |_| {
+            run_skipped(&window, &diagnostics);
+        });
+    }
+    {
+        let window = window.clone();
+        let current_working_dir = current_working_dir.to_path_buf();
+        let download_dir_ref = download_dir_ref.clone();
+        let command_send_channel = command_send_channel.clone();
+        let thread_set_scale = thread_set_scale.clone();
+        preferences_item.connect_activate(move |_| {
+            let old_config = config::current();
+            let new_config = match run_preferences(&window,
+                                                   &old_config,
+                                                   &list_themes(&current_working_dir)) {
+                Some(new_config) => new_config,
+                None => return,
+            };
+            if let Err(e) = config::set(new_config.clone()) {
+                warn!("{}", e);
+            }
+            // most settings are read where they are used, these need pushing
+            if new_config.download_dir != old_config.download_dir {
+                let new_dir = config::download_dir();
+                match fs::create_dir_all(&new_dir) {
+                    Ok(_) => {
+                        *download_dir_ref.lock().unwrap() = new_dir.clone();
+                        command_send_channel.send(GuiCmdMsg::ChangeDir(new_dir)).ignore();
+                    }
+                    Err(e) => warn!("Failed to create download dir {:?}: {}", new_dir, e),
+                }
+            }
+            if new_config.threads != old_config.threads {
+                // the scale passes it on to the download threads
+                thread_set_scale.get_adjustment()
+                                .set_value(configured_threads(threads) as f64);
+            }
+            if new_config.theme != old_config.theme {
+                if let Some(ref theme) = new_config.theme {
+                    if let Err(e) = choose_theme(&current_working_dir,
+                                                 &default_config_path,
+                                                 &secondary_config_path,
+                                                 theme) {
+                        warn!("{}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    let window_box = gtk::Box::new(Orientation::Vertical, 0);
+    window_box.pack_start(&menu_bar, false, false, 0);
+    window_box.pack_start(&lists_holder, true, true, 0);
+    window_box.pack_end(&statusbar, false, false, 0);
+    window.add(&window_box);
+
+    // restored before the list is saved again on close
+    let restored_ids = if config::startup().restore_selection {
+        catalog.previous_selection().into_iter().collect::<HashSet<u64>>()
+    } else {
+        HashSet::new()
+    };
 
     {
         let command_send_channel = command_send_channel.clone();
+        let categories = categories.clone();
+        let catalog = catalog.clone();
+        let category_store = category_store.clone();
         window.connect_delete_event(move |_, _| {
+            let data = categories.borrow();
             match command_send_channel.clone().send(GuiCmdMsg::Stop) {
                 Ok(_) => {}
-                Err(e) => println!("{:?}", e),
+                Err(e) => error!("{:?}", e),
+            }
+            let downloads = DOWNLOADS.lock().unwrap();
+            let unfinished = downloads.iter()
+                                      .filter(|download| !download.finished())
+                                      .map(|download| download.id())
+                                      .collect();
+            let snapshot = catalog.snapshot(&data,
+                                            &downloads,
+                                            unfinished,
+                                            checked_categories(&data, &category_store));
+            if let Err(e) = catalog::save_snapshot(&snapshot) {
+                error!("{}", e);
             }
             gtk::main_quit();
             Inhibit(false)
         });
     }
 
+    // Setup TLS
+    GTK_GLOBAL.with(move |gtk_global| {
+        *gtk_global.borrow_mut() = Some(GtkGlobal {
+            download_store: download_store,
+            download_filter: download_filter,
+            download_sort: download_sort,
+            group_store: group_store,
+            group_filter: group_filter,
+            group_sort: group_sort,
+            category_store: category_store,
+            update_recv: update_recv_channel,
+            statusbar: statusbar,
+            status_context: status_context,
+            downloadview: downloadview,
+            details: details,
+            command_send: command_send_channel.clone(),
+            collision_choice: Cell::new(None),
+            categories: categories,
+            url_siblings: url_siblings,
+            catalog: catalog,
+            changes: changes,
+            changes_item: changes_item,
+        });
+    });
+
+    if !args.enable.is_empty() || !restored_ids.is_empty() {
+        enable_downloads(data,
+                         |category, download| {
+                             restored_ids.contains(&download.id()) ||
+                             args.enable.iter().any(|name| category_matches(category.name(), name))
+                         },
+                         args.start,
+                         &category_store,
+                         &download_dir_ref.lock().unwrap(),
+                         &command_send_channel);
+    }
+
+    // new books in subscribed categories start right away
+    let subscriptions = refresh::subscriptions(&args.subscribe);
+    if !subscriptions.is_empty() && !new_ids.is_empty() {
+        enable_downloads(data,
+                         |category, download| {
+                             new_ids.contains(&download.id()) &&
+                             subscribed(category.name(), &subscriptions)
+                         },
+                         true,
+                         &category_store,
+                         &download_dir_ref.lock().unwrap(),
+                         &command_send_channel);
+    }
+
     window.show_all();
     gtk::main();
 }
 
-fn toggle_bool_iter(iter: &gtk::TreeIter, category_store: &gtk::TreeStore) {
-    let current_value = category_store.get_value(iter, 1)
+// queues and checks the books from --enable and the last run, paused unless start is set
+fn enable_downloads<F>(data: &[Category],
+                       wanted: F,
+                       start: bool,
+                       category_store: &gtk::TreeStore,
+                       download_dir: &Path,
+                       command_send_channel: &Sender<GuiCmdMsg>)
+                       -> usize
+    where F: Fn(&Category, &Download) -> bool
+{
+    let mut queued = 0;
+    for category in data.iter() {
+        let category_iter = category_iter(category_store, category.name());
+        for (download_idx, download) in category.downloads().iter().enumerate() {
+            if wanted(category, download) {
+                // paused from the start, a pause sent afterwards comes too late for the
+                // first few
+                let command = if start {
+                    GuiCmdMsg::Add(download.id(), download_dir.to_path_buf())
+                } else {
+                    GuiCmdMsg::AddPaused(download.id(), download_dir.to_path_buf())
+                };
+                command_send_channel.send(command).ignore();
+                queued += 1;
+                if let Some(book) = category_iter.as_ref().and_then(|category_iter| {
+                    category_store.iter_nth_child(Some(category_iter), download_idx as i32)
+                }) {
+                    category_store.set_value(&book, 1, &true.to_value());
+                }
+            }
+        }
+    }
+    update_category_checks(category_store);
+    queued
+}
+
+// names of the categories with at least one checked book
+fn checked_categories(data: &[Category], category_store: &gtk::TreeStore) -> Vec<String> {
+    data.iter()
+        .filter(|category| {
+            (0..category.downloads().len()).any(|download_idx| {
+                book_iter(category_store, category.name(), download_idx)
+                    .map(|iter| get_bool_iter(&iter, category_store, 1))
+                    .unwrap_or(false)
+            })
+        })
+        .map(|category| category.name().to_owned())
+        .collect()
+}
+
+// one entry per installed theme, the one in use is checked
+fn make_themes_menu(window: &gtk::Window,
+                    current_working_dir: PathBuf,
+                    default_config_path: PathBuf,
+                    secondary_config_path: PathBuf)
+                    -> gtk::MenuItem {
+    let themes_item = gtk::MenuItem::new_with_label("Themes");
+    let themes_menu = gtk::Menu::new();
+    let current_theme = read_theme_choice(&current_working_dir);
+    let theme_items = Rc::new(RefCell::new(Vec::new()));
+    // set_active emits activate as well, ignore the ones we cause ourselves
+    let switching = Rc::new(Cell::new(false));
+    let mut choices = vec![(AUTO_GTK_THEME.to_owned(), "Automatic (follow desktop)".to_owned())];
+    choices.extend(list_themes(&current_working_dir).into_iter().map(|name| (name.clone(), name)));
+    for (name, label) in choices {
+        let item = gtk::CheckMenuItem::new_with_label(&label);
+        item.set_draw_as_radio(true);
+        item.set_active(name == current_theme);
+        themes_menu.append(&item);
+        theme_items.borrow_mut().push((name.clone(), item.clone()));
+
+        let window = window.clone();
+        let current_working_dir = current_working_dir.clone();
+        let default_config_path = default_config_path.clone();
+        let secondary_config_path = secondary_config_path.clone();
+        let theme_items = theme_items.clone();
+        let switching = switching.clone();
+        item.connect_activate(move |item| {
+            if switching.get() {
+                return;
+            }
+            switching.set(true);
+            if item.get_active() {
+                match choose_theme(&current_working_dir,
+                                   &default_config_path,
+                                   &secondary_config_path,
+                                   &name) {
+                    Ok(_) => {
+                        info!("Switched to theme {}", name);
+                        if let Err(e) = save_theme_choice(&name) {
+                            warn!("{}", e);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        let dialog = gtk::MessageDialog::new(Some(&window),
+                                                             gtk::DialogFlags::empty(),
+                                                             gtk::MessageType::Error,
+                                                             gtk::ButtonsType::Ok,
+                                                             &format!("Could not load theme {}, \
+                                                                       keeping the current one.\n{}",
+                                                                      name,
+                                                                      e));
+                        dialog.run();
+                        dialog.destroy();
+                    }
+                }
+            }
+            sync_theme_items(&theme_items.borrow(), &read_theme_choice(&current_working_dir));
+            switching.set(false);
+        });
+    }
+    // the theme can also change from the preferences
+    themes_menu.connect_show(move |_| {
+        switching.set(true);
+        sync_theme_items(&theme_items.borrow(), &read_theme_choice(&current_working_dir));
+        switching.set(false);
+    });
+    themes_item.set_submenu(Some(&themes_menu));
+    themes_item
+}
+
+fn sync_theme_items(theme_items: &[(String, gtk::CheckMenuItem)], current_theme: &str) {
+    for &(ref theme, ref theme_item) in theme_items.iter() {
+        theme_item.set_active(theme == current_theme);
+    }
+}
+
+fn configured_threads(default_threads: usize) -> usize {
+    match config::with(|c| c.threads) {
+        0 => default_threads,
+        n => n,
+    }
+}
+
+// (category, book) positions of each url that's listed more than once
+fn url_siblings(data: &[Category]) -> HashMap<String, Vec<(usize, usize)>> {
+    let mut siblings = HashMap::new();
+    for (category_idx, category) in data.iter().enumerate() {
+        for (download_idx, download) in category.downloads().iter().enumerate() {
+            siblings.entry(normalize_url(download.url()))
+                    .or_insert(Vec::new())
+                    .push((category_idx, download_idx));
+        }
+    }
+    siblings.retain(|_, positions| positions.len() > 1);
+    siblings
+}
+
+// gives the other listings of each toggled book the same check, they share its file
+fn sync_url_siblings(data: &[Category],
+                     url_siblings: &HashMap<String, Vec<(usize, usize)>>,
+                     toggled: &[(usize, usize)],
+                     category_store: &gtk::TreeStore,
+                     command_send_channel: &Sender<GuiCmdMsg>,
+                     download_dir: &Path) {
+    for &(category_idx, download_idx) in toggled.iter() {
+        let download = data[category_idx].get_download_at_idx(download_idx);
+        let positions = match url_siblings.get(&normalize_url(download.url())) {
+            Some(positions) => positions,
+            None => continue,
+        };
+        let enabled = match book_iter(category_store,
+                                      data[category_idx].name(),
+                                      download_idx) {
+            Some(iter) => get_bool_iter(&iter, category_store, 1),
+            None => continue,
+        };
+        for &(other_category, other_download) in positions.iter() {
+            if (other_category, other_download) == (category_idx, download_idx) {
+                continue;
+            }
+            if let Some(iter) = book_iter(category_store,
+                                          data[other_category].name(),
+                                          other_download) {
+                if get_bool_iter(&iter, category_store, 1) != enabled {
+                    category_store.set_value(&iter, 1, &enabled.to_value());
+                    let sibling = data[other_category].get_download_at_idx(other_download);
+                    let command = if enabled {
+                        GuiCmdMsg::Add(sibling.id(), download_dir.to_path_buf())
+                    } else {
+                        GuiCmdMsg::Remove(sibling.id())
+                    };
+                    command_send_channel.send(command).ignore();
+                }
+            }
+        }
+    }
+    update_category_checks(category_store);
+}
+
+// a category's books come before its subcategories
+fn book_iter(category_store: &gtk::TreeStore,
+             category_name: &str,
+             download_idx: usize)
+             -> Option<gtk::TreeIter> {
+    category_iter(category_store, category_name).and_then(|category_iter| {
+        category_store.iter_nth_child(Some(&category_iter), download_idx as i32)
+    })
+}
+
+fn category_iter(category_store: &gtk::TreeStore, category_name: &str) -> Option<gtk::TreeIter> {
+    CATEGORY_ROWS.with(|rows| {
+        rows.borrow().get(category_name).and_then(|path| category_store.get_iter(path))
+    })
+}
+
+// remember where a category's row is, rows added after it don't move it
+fn index_category_row(category_store: &gtk::TreeStore, iter: &gtk::TreeIter) {
+    if let Some(path) = category_store.get_path(iter) {
+        CATEGORY_ROWS.with(|rows| {
+            rows.borrow_mut().insert(row_category_name(iter, category_store), path);
+        });
+    }
+}
+
+// after inserting rows in between, which moves the ones after them
+fn reindex_category_rows(category_store: &gtk::TreeStore) {
+    CATEGORY_ROWS.with(|rows| rows.borrow_mut().clear());
+    for_each_row(category_store, None, &mut |iter| {
+        if row_book_idx(iter, category_store).is_none() {
+            index_category_row(category_store, iter);
+        }
+    });
+}
+
+// every row under parent, depth first
+fn for_each_row<F>(category_store: &gtk::TreeStore, parent: Option<&gtk::TreeIter>, f: &mut F)
+    where F: FnMut(&gtk::TreeIter)
+{
+    if let Some(mut iter) = category_store.iter_children(parent) {
+        loop {
+            f(&iter);
+            for_each_row(category_store, Some(&iter), f);
+            if !category_store.iter_next(&mut iter) {
+                break;
+            }
+        }
+    }
+}
+
+// the full name of the category a row is or is in
+fn row_category_name(iter: &gtk::TreeIter, category_store: &gtk::TreeStore) -> String {
+    category_store.get_value(iter, COL_CATEGORY_NAME)
+                  .get::<String>()
+                  .unwrap_or(String::new())
+}
+
+// position of a book in its category, None on category rows
+fn row_book_idx(iter: &gtk::TreeIter, category_store: &gtk::TreeStore) -> Option<usize> {
+    match category_store.get_value(iter, COL_BOOK_IDX).get::<i32>() {
+        Some(idx) if idx >= 0 => Some(idx as usize),
+        _ => None,
+    }
+}
+
+// a category is checked once every book under it is, its subcategories' included
+fn update_category_checks(category_store: &gtk::TreeStore) {
+    update_checks_under(category_store, None);
+}
+
+// (any books under parent, all of them checked)
+fn update_checks_under(category_store: &gtk::TreeStore,
+                       parent: Option<&gtk::TreeIter>)
+                       -> (bool, bool) {
+    let mut has_books = false;
+    let mut all_checked = true;
+    if let Some(mut iter) = category_store.iter_children(parent) {
+        loop {
+            if row_book_idx(&iter, category_store).is_some() {
+                has_books = true;
+                all_checked = all_checked && get_bool_iter(&iter, category_store, 1);
+            } else {
+                let (sub_has_books, sub_all_checked) = update_checks_under(category_store,
+                                                                           Some(&iter));
+                category_store.set_value(&iter,
+                                         1,
+                                         &(sub_has_books && sub_all_checked).to_value());
+                has_books = has_books || sub_has_books;
+                all_checked = all_checked && sub_all_checked;
+            }
+            if !category_store.iter_next(&mut iter) {
+                break;
+            }
+        }
+    }
+    (has_books, all_checked)
+}
+
+fn get_bool_iter(iter: &gtk::TreeIter, category_store: &gtk::TreeStore, column: i32) -> bool {
+    category_store.get_value(iter, column)
         .get::<bool>()
-        .expect("No Value");
-    let new_value = (!current_value).to_value();
-    category_store.set_value(iter, 1, &new_value);
+        .unwrap_or(false)
+}
+
+// marks which rows match the search, a category stays visible while any row under it does
+// and shows everything under it when it matches itself
+fn filter_categories(category_store: &gtk::TreeStore, query: &str) {
+    filter_rows_under(category_store, None, false, query);
+}
+
+// returns whether any row under parent is visible
+fn filter_rows_under(category_store: &gtk::TreeStore,
+                     parent: Option<&gtk::TreeIter>,
+                     parent_matches: bool,
+                     query: &str)
+                     -> bool {
+    let mut any_visible = false;
+    if let Some(mut iter) = category_store.iter_children(parent) {
+        loop {
+            let matches = parent_matches || row_matches(&iter, category_store, query);
+            let child_visible = filter_rows_under(category_store, Some(&iter), matches, query);
+            let visible = matches || child_visible;
+            category_store.set_value(&iter, 2, &visible.to_value());
+            any_visible = any_visible || visible;
+            if !category_store.iter_next(&mut iter) {
+                break;
+            }
+        }
+    }
+    any_visible
+}
+
+fn row_matches(iter: &gtk::TreeIter, category_store: &gtk::TreeStore, query: &str) -> bool {
+    query.is_empty() ||
+    category_store.get_value(iter, 3)
+                  .get::<String>()
+                  .map(|haystack| haystack.contains(query))
+                  .unwrap_or(false)
 }
 
 lazy_static! {
     static ref DOWNLOADS: Mutex<Vec<Download>> = Mutex::new(Vec::new());
     static ref ID_DOWNLOAD_HM: Mutex<HashMap<u64, Download>> = Mutex::new(HashMap::new());
+    static ref DOWNLOAD_FILTER: Mutex<StateFilter> = Mutex::new(StateFilter::All);
+    static ref GROUP_BY_CATEGORY: Mutex<bool> = Mutex::new(false);
+}
+
+// quick filters over the download list
+#[derive(Clone, Copy, PartialEq)]
+enum StateFilter {
+    All,
+    Active,
+    Queued,
+    Failed,
+    Done,
+}
+
+const STATE_FILTERS: [(&'static str, StateFilter); 5] = [("All", StateFilter::All),
+                                                         ("Active", StateFilter::Active),
+                                                         ("Queued", StateFilter::Queued),
+                                                         ("Failed", StateFilter::Failed),
+                                                         ("Done", StateFilter::Done)];
+
+impl StateFilter {
+    fn allows(&self, state: DownloadState) -> bool {
+        match *self {
+            StateFilter::All => true,
+            StateFilter::Active => state == DownloadState::Downloading,
+            // paused downloads are waiting just like queued ones
+            StateFilter::Queued => state == DownloadState::Queued || state == DownloadState::Paused,
+            StateFilter::Failed => state == DownloadState::Failed,
+            StateFilter::Done => state == DownloadState::Finished,
+        }
+    }
+}
+
+// recompute the visible column after the quick filter changed
+fn refilter_downloads(download_store: &gtk::ListStore, group_store: &gtk::TreeStore) {
+    let filter = *DOWNLOAD_FILTER.lock().unwrap();
+    // the grouped rows catch up when they're shown
+    let grouped = *GROUP_BY_CATEGORY.lock().unwrap();
+    // collected first, hiding a selected row fires the selection handler which locks DOWNLOADS
+    let visible = DOWNLOADS.lock()
+                           .unwrap()
+                           .iter()
+                           .map(|dl| {
+                               (dl.id(), dl.state().map(|state| filter.allows(state)).unwrap_or(false))
+                           })
+                           .collect::<Vec<(u64, bool)>>();
+    for (idx, &(id, visible)) in visible.iter().enumerate() {
+        if let Some(iter) = download_store.iter_nth_child(None, idx as i32) {
+            download_store.set_value(&iter, COL_VISIBLE, &visible.to_value());
+        }
+        if grouped {
+            if let Some(iter) = grouped_row(id) {
+                group_store.set_value(&iter, COL_VISIBLE, &visible.to_value());
+            }
+        }
+    }
+}
+
+// category a download is listed under in grouped mode
+fn group_name(download: &Download) -> String {
+    download.category_name().unwrap_or(UNCATEGORIZED.to_owned())
+}
+
+fn group_heading(category: &str) -> Option<gtk::TreeIter> {
+    GROUP_HEADINGS.with(|headings| headings.borrow().get(category).cloned())
+}
+
+fn grouped_row(id: u64) -> Option<gtk::TreeIter> {
+    GROUP_ROWS.with(|rows| rows.borrow().get(&id).cloned())
+}
+
+fn add_grouped_download(group_store: &gtk::TreeStore, download: &Download) {
+    let category = group_name(download);
+    let category_iter = match group_heading(&category) {
+        Some(iter) => iter,
+        None => {
+            let iter = group_store.append(None);
+            group_store.set_value(&iter, COL_GROUP, &category.to_value());
+            GROUP_HEADINGS.with(|headings| {
+                headings.borrow_mut().insert(category.clone(), iter.clone())
+            });
+            iter
+        }
+    };
+    if let Some((_, values)) = download_to_values(download) {
+        let iter = group_store.append(Some(&category_iter));
+        group_store.set_value(&iter, COL_GROUP, &category.to_value());
+        group_store.set_download(&iter, values);
+        GROUP_ROWS.with(|rows| rows.borrow_mut().insert(download.id(), iter));
+    }
+}
+
+fn remove_grouped_download(group_store: &gtk::TreeStore, download: &Download) {
+    if let Some(mut iter) = GROUP_ROWS.with(|rows| rows.borrow_mut().remove(&download.id())) {
+        group_store.remove(&mut iter);
+    }
+    // drop the category row along with its last download
+    let category = group_name(download);
+    if let Some(mut category_iter) = group_heading(&category) {
+        if group_store.iter_n_children(Some(&category_iter)) == 0 {
+            GROUP_HEADINGS.with(|headings| headings.borrow_mut().remove(&category));
+            group_store.remove(&mut category_iter);
+        }
+    }
+}
+
+// the grouped rows aren't kept up while the flat list is shown, this brings them back
+fn sync_grouped_downloads(group_store: &gtk::TreeStore) {
+    for download in DOWNLOADS.lock().unwrap().iter() {
+        if let (Some(iter), Some((_, values))) = (grouped_row(download.id()),
+                                                  download_to_values(download)) {
+            group_store.set_download(&iter, values);
+        }
+    }
+    let categories = GROUP_HEADINGS.with(|headings| {
+        headings.borrow().keys().cloned().collect::<Vec<String>>()
+    });
+    for category in categories.iter() {
+        show_group_totals(group_store, category);
+    }
+}
+
+// totals over the downloads of a category
+#[derive(Clone)]
+struct CategoryTotals {
+    count: usize,
+    done: usize,
+    progress: usize,
+    total: usize,
+    speed: f32,
+}
+
+impl CategoryTotals {
+    fn new() -> CategoryTotals {
+        CategoryTotals {
+            count: 0,
+            done: 0,
+            progress: 0,
+            total: 0,
+            speed: 0.0,
+        }
+    }
+
+    // counts a download in, or back out with its values from before a change
+    fn tally(&mut self, download: &Download, added: bool) {
+        if let &Some(ref info) = download.download_info() {
+            let done = if info.finished() { 1 } else { 0 };
+            if added {
+                self.count += 1;
+                self.done += done;
+                self.progress += info.progress();
+                self.total += info.total();
+                self.speed += info.speed();
+            } else {
+                self.count = self.count.saturating_sub(1);
+                self.done = self.done.saturating_sub(done);
+                self.progress = self.progress.saturating_sub(info.progress());
+                self.total = self.total.saturating_sub(info.total());
+                self.speed = maximum(self.speed - info.speed(), 0.0);
+            }
+        }
+    }
+
+    fn percentage(&self) -> f32 {
+        minimum(self.progress as f32 / maximum(self.total as f32, 1.0), 1.0)
+    }
+
+    fn count_text(&self) -> String {
+        format!("{}/{}", self.done, self.count)
+    }
+
+    fn to_row(&self, category: &str) -> DownloadRow {
+        let remaining = self.total.saturating_sub(self.progress);
+        let eta_seconds = if remaining == 0 {
+            Some(0.0)
+        } else if self.speed == 0.0 {
+            None
+        } else {
+            Some(remaining as f64 / self.speed as f64)
+        };
+        DownloadRow {
+            name: format!("{} ({})", category, self.count_text()),
+            size: (self.total as f32).convert_to_byte_units(0),
+            progress: self.percentage() * 100.0,
+            speed: format!("{}/s", self.speed.convert_to_byte_units(0)),
+            eta: eta_seconds.map(format_duration).unwrap_or("∞".to_owned()),
+            history: String::new(),
+            id: 0,
+            raw_size: self.total as u64,
+            raw_speed: self.speed,
+            raw_eta: eta_seconds.unwrap_or(UNKNOWN_ETA),
+            state: DownloadState::Downloading,
+        }
+    }
+}
+
+// books a refresh found go under their categories, which are added if they're new too
+fn add_refreshed_books(global: &GtkGlobal, downloads: &[Download]) {
+    let category_store = &global.category_store;
+    let mut categories = global.categories.borrow_mut();
+    // new categories are appended, which leaves the other rows where they were
+    let mut books = Vec::new();
+    for download in downloads.iter().filter(|download| !download.category_path().is_empty()) {
+        let category_idx = find_or_add_category(&mut categories,
+                                                category_store,
+                                                download.category_path());
+        let category_name = categories[category_idx].name().to_owned();
+        if let Some(iter) = category_iter(category_store, &category_name) {
+            books.push((category_name, iter, download));
+        }
+    }
+    // books come before the subcategories, so these move rows, the iters stay good
+    for (category_name, iter, download) in books {
+        if let Some(category) = categories.iter_mut().find(|c| c.name() == category_name) {
+            let book_idx = category.downloads().len();
+            let book = category_store.insert(Some(&iter), book_idx as i32);
+            category_store.set_book(&book, download, &category_name, book_idx);
+            category.push_download(download.clone());
+        }
+    }
+    reindex_category_rows(category_store);
+    update_category_checks(category_store);
+
+    // positions moved, and the new books count as changes since the last run
+    *global.url_siblings.borrow_mut() = url_siblings(&categories);
+    let changes = global.catalog.changes(&categories);
+    global.changes_item.set_label(&format!("Changes Since Last Run ({})", changes.len()));
+    *global.changes.borrow_mut() = changes;
+}
+
+// position of the category with that path, a new one goes at the end of its parent
+fn find_or_add_category(categories: &mut Vec<Category>,
+                        category_store: &gtk::TreeStore,
+                        path: &[String])
+                        -> usize {
+    let category = Category::new(path.to_vec(), Vec::new());
+    if let Some(idx) = categories.iter().position(|c| c.name() == category.name()) {
+        return idx;
+    }
+    let (idx, parent) = if path.len() > 1 {
+        let parent_idx = find_or_add_category(categories, category_store, &path[..path.len() - 1]);
+        (subtree(categories, parent_idx).end,
+         category_iter(category_store, categories[parent_idx].name()))
+    } else {
+        (categories.len(), None)
+    };
+    category_store.add_category(&category, parent.as_ref());
+    categories.insert(idx, category);
+    idx
+}
+
+// counts a download in or out of its category in the grouped list, and of every heading
+// above it in the sidebar
+fn count_download(download: &Download, added: bool) {
+    GROUP_TOTALS.with(|totals| {
+        totals.borrow_mut()
+              .entry(group_name(download))
+              .or_insert_with(CategoryTotals::new)
+              .tally(download, added);
+    });
+    let path = download.category_path();
+    HEADING_TOTALS.with(|totals| {
+        let mut totals = totals.borrow_mut();
+        for depth in 1..path.len() + 1 {
+            totals.entry(path[..depth].join(CATEGORY_SEPARATOR))
+                  .or_insert_with(CategoryTotals::new)
+                  .tally(download, added);
+        }
+    });
+}
+
+fn show_group_totals(group_store: &gtk::TreeStore, category: &str) {
+    if let Some(iter) = group_heading(category) {
+        let totals = GROUP_TOTALS.with(|totals| totals.borrow().get(category).cloned())
+                                 .unwrap_or(CategoryTotals::new());
+        group_store.set_download(&iter, totals.to_row(category));
+        // category rows stay up whatever the quick filter is
+        group_store.set_value(&iter, COL_VISIBLE, &true.to_value());
+    }
+}
+
+// refresh the grouped category row and the sidebar after one of its downloads changed
+fn update_category_totals(global: &GtkGlobal, download: &Download) {
+    if *GROUP_BY_CATEGORY.lock().unwrap() {
+        show_group_totals(&global.group_store, &group_name(download));
+    }
+    let category_store = &global.category_store;
+    let path = download.category_path();
+    for depth in 1..path.len() + 1 {
+        let heading = path[..depth].join(CATEGORY_SEPARATOR);
+        if let Some(iter) = category_iter(category_store, &heading) {
+            let totals = HEADING_TOTALS.with(|totals| totals.borrow().get(&heading).cloned())
+                                       .unwrap_or(CategoryTotals::new());
+            category_store.set_value(&iter, 4, &(totals.percentage() * 100.0).to_value());
+            category_store.set_value(&iter, 5, &totals.count_text().to_value());
+            category_store.set_value(&iter, 6, &(totals.count > 0).to_value());
+        }
+    }
+}
+
+fn load_sort_order() -> Option<(i32, gtk::SortType)> {
+    config::with(|c| {
+        c.sort_column.map(|column| {
+            let order = if c.sort_descending {
+                gtk::SortType::Descending
+            } else {
+                gtk::SortType::Ascending
+            };
+            (column, order)
+        })
+    })
+}
+
+fn save_sort_order(column: i32, order: gtk::SortType) -> Result<(), String> {
+    config::update(|c| {
+        c.sort_column = Some(column);
+        c.sort_descending = order == gtk::SortType::Descending;
+    })
 }
 
 // Threadlocal storage of Gtk Stuff
 thread_local!{
-    // (main data, download store, message receiver)
-    static GTK_GLOBAL: RefCell<Option<(gtk::ListStore, Receiver<GuiUpdateMsg>)>> = RefCell::new(None)
+    static GTK_GLOBAL: RefCell<Option<GtkGlobal>> = RefCell::new(None);
+    // category name -> its row in the sidebar
+    static CATEGORY_ROWS: RefCell<HashMap<String, gtk::TreePath>> = RefCell::new(HashMap::new());
+    // rows of the grouped list, by download id and by category
+    static GROUP_ROWS: RefCell<HashMap<u64, gtk::TreeIter>> = RefCell::new(HashMap::new());
+    static GROUP_HEADINGS: RefCell<HashMap<String, gtk::TreeIter>> = RefCell::new(HashMap::new());
+    // kept up with every change rather than summed over DOWNLOADS again, by category for
+    // the grouped list and with the subcategories counted in for the sidebar
+    static GROUP_TOTALS: RefCell<HashMap<String, CategoryTotals>> = RefCell::new(HashMap::new());
+    static HEADING_TOTALS: RefCell<HashMap<String, CategoryTotals>> = RefCell::new(HashMap::new())
+}
+
+struct GtkGlobal {
+    download_store: gtk::ListStore,
+    download_filter: gtk::TreeModelFilter,
+    download_sort: gtk::TreeModelSort,
+    group_store: gtk::TreeStore,
+    group_filter: gtk::TreeModelFilter,
+    group_sort: gtk::TreeModelSort,
+    category_store: gtk::TreeStore,
+    update_recv: Receiver<GuiUpdateMsg>,
+    statusbar: gtk::Statusbar,
+    status_context: u32,
+    downloadview: gtk::TreeView,
+    details: DetailsPane,
+    command_send: Sender<GuiCmdMsg>,
+    // answer to every further collision this session, from the collision dialog
+    collision_choice: Cell<Option<CollisionPolicy>>,
+    // the list as the sidebar has it, refreshes add to it
+    categories: Rc<RefCell<Vec<Category>>>,
+    url_siblings: Rc<RefCell<HashMap<String, Vec<(usize, usize)>>>>,
+    catalog: Rc<Catalog>,
+    changes: Rc<RefCell<Vec<CatalogChange>>>,
+    changes_item: gtk::MenuItem,
 }
 
 // update TLS
 fn update_local() -> Continue {
     GTK_GLOBAL.with(|gtk_global| {
-        if let Some((ref download_store, ref rx)) = *gtk_global.borrow() {
+        if let Some(ref global) = *gtk_global.borrow() {
+            let download_store = &global.download_store;
+            let rx = &global.update_recv;
             if let Ok(changes) = rx.try_recv() {
                 // clear and repopulate takes far too long
                 // for every change made in commhandler, append to change list
@@ -435,18 +1603,23 @@ fn update_local() -> Continue {
                     match change {
                         &GuiChange::Remove(idx) => {
                             // remove index
+                            // DOWNLOADS goes first, removing the row fires the selection handler
+                            let removed = DOWNLOADS.lock().unwrap().remove(idx);
                             let mut iter = download_store.iter_nth_child(None, idx as i32)
                                 .expect("no such iter");
                             download_store.remove(&mut iter);
-                            DOWNLOADS.lock().unwrap().remove(idx);
+                            remove_grouped_download(&global.group_store, &removed);
+                            count_download(&removed, false);
+                            update_category_totals(global, &removed);
                         }
                         &GuiChange::Add(ref download) => {
-                            let mut download = download.clone();
-                            download.start_download();
-                            download.set_enable_state(true);
+                            let download = download.clone();
                             // add download
                             let values = download_to_values(&download).unwrap().1;
                             download_store.add_download(values);
+                            add_grouped_download(&global.group_store, &download);
+                            count_download(&download, true);
+                            update_category_totals(global, &download);
                             DOWNLOADS.lock().unwrap().push(download);
                         }
                         &GuiChange::Set(idx, ref download) => {
@@ -454,20 +1627,58 @@ fn update_local() -> Continue {
                                 .expect("no such iter");
                             let values = download_to_values(&download).unwrap().1;
                             download_store.set_download(&iter, values);
+                            if let Some(stored) = DOWNLOADS.lock().unwrap().get_mut(idx) {
+                                count_download(stored, false);
+                                *stored = download.clone();
+                            }
+                            count_download(download, true);
+                            // the grouped rows catch up when they're shown
+                            if *GROUP_BY_CATEGORY.lock().unwrap() {
+                                if let Some(iter) = grouped_row(download.id()) {
+                                    let values = download_to_values(&download).unwrap().1;
+                                    global.group_store.set_download(&iter, values);
+                                }
+                            }
+                            update_category_totals(global, download);
+                            if selected_index(&global.downloadview.get_selection()) == Some(idx) {
+                                global.details.show(download);
+                            }
                         }
                         &GuiChange::Open(ref file_url) => {
                             let basepath = Path::new(file_url);
-                            let parent = basepath.parent().unwrap();
-                            let fileprefix;
-                            if cfg!(target_os = "windows") {
-                                fileprefix = "file:\\\\";
-                            } else {
-                                fileprefix = "file://";
-                            }
-                            let urlstr = CString::new(format!("{}{}", fileprefix, parent.to_str().unwrap())).unwrap();
-                            unsafe {
-                                g_app_info_launch_default_for_uri(urlstr.as_ptr(), null_mut(), null_mut());
-                            }
+                            launch_default_for_path(basepath.parent().unwrap());
+                        }
+                        &GuiChange::OpenFile(ref file_url) => {
+                            launch_default_for_path(Path::new(file_url));
+                        }
+                        &GuiChange::Summary(ref summary) => {
+                            global.statusbar.remove_all(global.status_context);
+                            global.statusbar.push(global.status_context, &summary_to_string(summary));
+                        }
+                        &GuiChange::Extend(ref downloads) => add_refreshed_books(global, downloads),
+                        &GuiChange::Notice(ref message) => {
+                            let dialog = gtk::MessageDialog::new(None::<&gtk::Window>,
+                                                                 gtk::DialogFlags::empty(),
+                                                                 gtk::MessageType::Info,
+                                                                 gtk::ButtonsType::Ok,
+                                                                 message);
+                            dialog.run();
+                            dialog.destroy();
+                        }
+                        &GuiChange::Collision(id, ref question) => {
+                            let policy = match global.collision_choice.get() {
+                                Some(policy) => policy,
+                                None => {
+                                    let (policy, remember) = ask_collision(question);
+                                    if remember {
+                                        global.collision_choice.set(Some(policy));
+                                    }
+                                    policy
+                                }
+                            };
+                            global.command_send
+                                  .send(GuiCmdMsg::ResolveCollision(id, policy))
+                                  .ignore();
                         }
                         &GuiChange::Panicked(is_downloader, ref error) => {
                             if is_downloader {
@@ -494,6 +1705,206 @@ fn update_local() -> Continue {
     Continue(false)
 }
 
+// asks what to do with a book whose file is already taken, and whether to keep doing it
+fn ask_collision(question: &str) -> (CollisionPolicy, bool) {
+    let dialog = gtk::MessageDialog::new(None::<&gtk::Window>,
+                                         gtk::DialogFlags::empty(),
+                                         gtk::MessageType::Question,
+                                         gtk::ButtonsType::None,
+                                         question);
+    let choices = COLLISION_POLICIES.iter()
+                                    .filter(|&&(_, policy)| policy != CollisionPolicy::Ask)
+                                    .collect::<Vec<_>>();
+    let buttons = choices.iter()
+                         .enumerate()
+                         .map(|(response, &&(label, _))| (label, response as i32))
+                         .collect::<Vec<_>>();
+    dialog.add_buttons(&buttons);
+    let remember_check = gtk::CheckButton::new_with_label("Do the same for the rest");
+    if let Some(content_area) = dialog.get_content_area() {
+        content_area.add(&remember_check);
+    }
+    remember_check.show();
+    let response = dialog.run();
+    let remember = remember_check.get_active();
+    dialog.destroy();
+    // closing the dialog numbers the file rather than losing the book
+    let policy = choices.get(response as usize)
+                        .map(|&&(_, policy)| policy)
+                        .unwrap_or(CollisionPolicy::Suffix);
+    (policy, remember)
+}
+
+// context menu for a single download row
+fn popup_download_menu(idx: usize, time: u32, command_send_channel: Sender<GuiCmdMsg>) {
+    let state = DOWNLOADS.lock()
+                         .unwrap()
+                         .get(idx)
+                         .and_then(|download| download.state());
+    let is = |states: &[DownloadState]| {
+        state.map(|s| states.contains(&s)).unwrap_or(false)
+    };
+    // (name, enabled for this row's state)
+    let menu_buttons = [("Open File", is(&[DownloadState::Finished])),
+                        ("Open Directory", true),
+                        ("Copy URL", true),
+                        ("Copy Path", true),
+                        ("Pause", is(&[DownloadState::Queued, DownloadState::Downloading])),
+                        ("Resume", is(&[DownloadState::Paused])),
+                        // a running download has to be paused or cancelled first
+                        ("Restart", !is(&[DownloadState::Queued, DownloadState::Downloading])),
+                        ("Move to Top", is(&[DownloadState::Queued])),
+                        ("Move to Bottom", is(&[DownloadState::Queued])),
+                        ("Verify", is(&[DownloadState::Finished])),
+                        ("Delete File from Disk",
+                         is(&[DownloadState::Finished, DownloadState::Failed, DownloadState::Paused])),
+                        ("Cancel", true)];
+
+    let right_click_menu = gtk::Menu::new();
+    for &(name, enabled) in menu_buttons.iter() {
+        let item = gtk::MenuItem::new_with_label(name);
+        item.set_name(name);
+        item.set_sensitive(enabled);
+        right_click_menu.append(&item);
+    }
+    // null item workaround
+    let null_item = gtk::MenuItem::new_with_label("");
+    null_item.set_name("None");
+    right_click_menu.add(&null_item);
+    right_click_menu.set_active(menu_buttons.len() as u32);
+    right_click_menu.show_all();
+    null_item.hide();
+    right_click_menu.popup(3, time);
+    right_click_menu.connect_hide(move |this| {
+        if let Some(selection) = this.get_active() {
+            let command = match &selection.get_name().unwrap() as &str {
+                "Open File" => Some(GuiCmdMsg::OpenFile(idx)),
+                "Open Directory" => Some(GuiCmdMsg::Open(idx)),
+                "Copy URL" => {
+                    if let Some(download) = DOWNLOADS.lock().unwrap().get(idx) {
+                        copy_to_clipboard(download.url());
+                    }
+                    None
+                }
+                "Copy Path" => {
+                    if let Some(download) = DOWNLOADS.lock().unwrap().get(idx) {
+                        if download.downloading() {
+                            copy_to_clipboard(&download.file_path().to_string_lossy());
+                        }
+                    }
+                    None
+                }
+                "Pause" => Some(GuiCmdMsg::Pause(idx)),
+                "Resume" => Some(GuiCmdMsg::Resume(idx)),
+                "Restart" => Some(GuiCmdMsg::Restart(idx)),
+                "Move to Top" => Some(GuiCmdMsg::MoveToTop(idx)),
+                "Move to Bottom" => Some(GuiCmdMsg::MoveToBottom(idx)),
+                "Verify" => Some(GuiCmdMsg::Verify(idx)),
+                "Delete File from Disk" => Some(GuiCmdMsg::DeleteFile(idx)),
+                "Cancel" => Some(GuiCmdMsg::Cancel(idx)),
+                _ => None,
+            };
+            if let Some(command) = command {
+                command_send_channel.send(command).ignore();
+            }
+        }
+        this.destroy();
+    });
+}
+
+// context menu for several selected rows, sent as one batched command
+fn popup_batch_menu(indices: &[usize], time: u32, command_send_channel: Sender<GuiCmdMsg>) {
+    let ids = {
+        let downloads = DOWNLOADS.lock().unwrap();
+        indices.iter().filter_map(|&idx| downloads.get(idx).map(|dl| dl.id())).collect::<Vec<u64>>()
+    };
+    let menu_buttons = [("Cancel", BatchAction::Cancel),
+                        ("Restart", BatchAction::Restart),
+                        ("Pause", BatchAction::Pause),
+                        ("Resume", BatchAction::Resume),
+                        ("Remove", BatchAction::Remove),
+                        ("Open", BatchAction::Open)];
+    let right_click_menu = gtk::Menu::new();
+    for &(name, _) in menu_buttons.iter() {
+        let item = gtk::MenuItem::new_with_label(&format!("{} {} Downloads", name, ids.len()));
+        item.set_name(name);
+        right_click_menu.append(&item);
+    }
+    // null item workaround
+    let null_item = gtk::MenuItem::new_with_label("");
+    null_item.set_name("None");
+    right_click_menu.add(&null_item);
+    right_click_menu.set_active(menu_buttons.len() as u32);
+    right_click_menu.show_all();
+    null_item.hide();
+    right_click_menu.popup(3, time);
+    right_click_menu.connect_hide(move |this| {
+        if let Some(selection) = this.get_active() {
+            let name = selection.get_name().unwrap();
+            if let Some(&(_, action)) = menu_buttons.iter().find(|&&(n, _)| n == name) {
+                command_send_channel.send(GuiCmdMsg::Batch(action, ids.clone())).ignore();
+            }
+        }
+        this.destroy();
+    });
+}
+
+fn launch_default_for_path(path: &Path) {
+    let fileprefix;
+    if cfg!(target_os = "windows") {
+        fileprefix = "file:\\\\";
+    } else {
+        fileprefix = "file://";
+    }
+    let urlstr = CString::new(format!("{}{}", fileprefix, path.to_str().unwrap())).unwrap();
+    unsafe {
+        g_app_info_launch_default_for_uri(urlstr.as_ptr(), null_mut(), null_mut());
+    }
+}
+
+// the view shows the store filtered then sorted, map a view path back to its store row
+fn store_index(path: &gtk::TreePath) -> Option<usize> {
+    GTK_GLOBAL.with(|gtk_global| {
+        gtk_global.borrow().as_ref().and_then(|global| {
+            if *GROUP_BY_CATEGORY.lock().unwrap() {
+                let group_store = &global.group_store;
+                global.group_sort
+                      .convert_path_to_child_path(path)
+                      .and_then(|path| global.group_filter.convert_path_to_child_path(&path))
+                      // category rows aren't downloads
+                      .and_then(|path| if path.get_depth() == 2 {
+                          group_store.get_iter(&path)
+                      } else {
+                          None
+                      })
+                      .and_then(|iter| group_store.get_value(&iter, COL_ID).get::<u64>())
+                      .and_then(|id| DOWNLOADS.lock().unwrap().iter().position(|dl| dl.id() == id))
+            } else {
+                global.download_sort
+                      .convert_path_to_child_path(path)
+                      .and_then(|path| global.download_filter.convert_path_to_child_path(&path))
+                      .map(|path| path.get_indices()[0] as usize)
+            }
+        })
+    })
+}
+
+// store rows of the selected downloads
+fn selected_indices(selection: &gtk::TreeSelection) -> Vec<usize> {
+    let (paths, _) = selection.get_selected_rows();
+    paths.iter().filter_map(store_index).collect()
+}
+
+// index of the selected download row, if exactly one is selected
+fn selected_index(selection: &gtk::TreeSelection) -> Option<usize> {
+    let selected = selected_indices(selection);
+    if selected.len() == 1 {
+        Some(selected[0])
+    } else {
+        None
+    }
+}
+
 pub fn update_gui() {
     if gtk::is_initialized() {
         glib::idle_add(update_local);
@@ -520,56 +1931,148 @@ fn update_download(sender: Sender<GuiCmdMsg>,
     }
 
 trait AddCategories {
-    fn add_category(&self, category: &Category);
+    fn add_category(&self, category: &Category, parent: Option<&gtk::TreeIter>) -> gtk::TreeIter;
+    fn set_book(&self, iter: &gtk::TreeIter, download: &Download, category_name: &str, idx: usize);
     fn add_categories(&self, categories: &[Category]);
 }
 
 impl AddCategories for gtk::TreeStore {
-    fn add_category(&self, category: &Category) {
+    fn add_category(&self, category: &Category, parent: Option<&gtk::TreeIter>) -> gtk::TreeIter {
         let category_name = category.name();
         let downloads = category.downloads();
-        let iter = self.append(None);
+        let iter = self.append(parent);
         let category_download_bool = category.enabled().to_value();
-        self.set_value(&iter, 0, &category_name.to_value());
+        self.set_value(&iter, 0, &category.title().to_value());
         self.set_value(&iter, 1, &category_download_bool);
+        self.set_value(&iter, 2, &true.to_value());
+        self.set_value(&iter, 3, &category_name.to_lowercase().to_value());
+        self.set_value(&iter, 4, &0f32.to_value());
+        self.set_value(&iter, 5, &"".to_value());
+        self.set_value(&iter, 6, &false.to_value());
+        self.set_value(&iter, COL_CATEGORY_NAME, &category_name.to_value());
+        self.set_value(&iter, COL_BOOK_IDX, &(-1i32).to_value());
+        index_category_row(self, &iter);
         // add all of the downloads
-        for download in downloads.iter() {
-            let download_name = download.name();
-            let download_download_bool = download.enabled().to_value();
+        for (idx, download) in downloads.iter().enumerate() {
             let child_iter = self.append(Some(&iter));
-            self.set_value(&child_iter, 0, &download_name.to_value());
-            self.set_value(&child_iter, 1, &download_download_bool);
+            self.set_book(&child_iter, download, category_name, idx);
         }
+        iter
+    }
+
+    fn set_book(&self, iter: &gtk::TreeIter, download: &Download, category_name: &str, idx: usize) {
+        self.set_value(iter, 0, &download.name().to_value());
+        self.set_value(iter, 1, &download.enabled().to_value());
+        self.set_value(iter, 2, &true.to_value());
+        self.set_value(iter, 3, &search_text(download).to_value());
+        self.set_value(iter, 4, &0f32.to_value());
+        self.set_value(iter, 5, &"".to_value());
+        self.set_value(iter, 6, &false.to_value());
+        self.set_value(iter, COL_CATEGORY_NAME, &category_name.to_value());
+        self.set_value(iter, COL_BOOK_IDX, &(idx as i32).to_value());
     }
 
+    // subcategories go under the last shallower category
     fn add_categories(&self, categories: &[Category]) {
+        let mut parents: Vec<(usize, gtk::TreeIter)> = Vec::new();
         for category in categories.iter() {
-            self.add_category(category);
+            while parents.last().map(|&(depth, _)| depth >= category.depth()).unwrap_or(false) {
+                parents.pop();
+            }
+            let iter = self.add_category(category, parents.last().map(|&(_, ref iter)| iter));
+            parents.push((category.depth(), iter));
         }
+        update_category_checks(self);
     }
 }
 
+// hidden sidebar columns
+const COL_CATEGORY_NAME: i32 = 7;
+const COL_BOOK_IDX: i32 = 8;
+
+// hidden download list columns
+const COL_ID: i32 = 6;
+const COL_RAW_SIZE: i32 = 7;
+const COL_RAW_SPEED: i32 = 8;
+const COL_RAW_ETA: i32 = 9;
+const COL_VISIBLE: i32 = 10;
+// only in the grouped store
+const COL_GROUP: i32 = 11;
+
+// grouped mode heading for downloads without a category
+const UNCATEGORIZED: &'static str = "Uncategorized";
+
+// downloads with no estimate sort after every known eta
+const UNKNOWN_ETA: f64 = 1e18;
+
+struct DownloadRow {
+    name: String,
+    size: String,
+    progress: f32,
+    speed: String,
+    eta: String,
+    history: String,
+    id: u64,
+    raw_size: u64,
+    raw_speed: f32,
+    raw_eta: f64,
+    state: DownloadState,
+}
+
+// everything a book can be searched by, lowercased
+fn search_text(download: &Download) -> String {
+    [download.name(),
+     download.author().unwrap_or(""),
+     url_host(download.url()).unwrap_or(""),
+     download.category_name().unwrap_or(String::new()).as_str()]
+        .join("\n")
+        .to_lowercase()
+}
+
 trait AddDownload {
-    fn add_download(&self, download: (String, String, f32, String, String));
-    fn set_download(&self, iter: &gtk::TreeIter, download: (String, String, f32, String, String));
+    fn add_download(&self, download: DownloadRow);
 }
 
 impl AddDownload for gtk::ListStore {
-    fn add_download(&self, download: (String, String, f32, String, String)) {
+    fn add_download(&self, download: DownloadRow) {
         let iter = self.append();
         self.set_download(&iter, download);
     }
-    fn set_download(&self, iter: &gtk::TreeIter, download: (String, String, f32, String, String)) {
-        self.set_value(&iter, 0, &download.0.to_value());
-        self.set_value(&iter, 1, &download.1.to_value());
-        self.set_value(&iter, 2, &download.2.to_value());
-        self.set_value(&iter, 3, &download.3.to_value());
-        self.set_value(&iter, 4, &download.4.to_value());
+}
+
+// the flat and the grouped store share their download columns
+trait SetDownload {
+    fn set_download(&self, iter: &gtk::TreeIter, download: DownloadRow);
+}
+
+impl SetDownload for gtk::ListStore {
+    fn set_download(&self, iter: &gtk::TreeIter, download: DownloadRow) {
+        set_download_values(download, |column, value| self.set_value(iter, column, value));
+    }
+}
+
+impl SetDownload for gtk::TreeStore {
+    fn set_download(&self, iter: &gtk::TreeIter, download: DownloadRow) {
+        set_download_values(download, |column, value| self.set_value(iter, column, value));
     }
 }
 
-// name, size, progress, speed, eta
-fn make_liststore_model(data: &Vec<Download>) -> HashMap<u64, (String, String, f32, String, String)> {
+fn set_download_values<F: Fn(i32, &glib::Value)>(download: DownloadRow, set_value: F) {
+    let visible = DOWNLOAD_FILTER.lock().unwrap().allows(download.state);
+    set_value(0, &download.name.to_value());
+    set_value(1, &download.size.to_value());
+    set_value(2, &download.progress.to_value());
+    set_value(3, &download.speed.to_value());
+    set_value(4, &download.eta.to_value());
+    set_value(5, &download.history.to_value());
+    set_value(COL_ID, &download.id.to_value());
+    set_value(COL_RAW_SIZE, &download.raw_size.to_value());
+    set_value(COL_RAW_SPEED, &download.raw_speed.to_value());
+    set_value(COL_RAW_ETA, &download.raw_eta.to_value());
+    set_value(COL_VISIBLE, &visible.to_value());
+}
+
+fn make_liststore_model(data: &Vec<Download>) -> HashMap<u64, DownloadRow> {
     let mut items = HashMap::new();
     for dl in data.iter() {
         match download_to_values(dl) {
@@ -582,19 +2085,45 @@ fn make_liststore_model(data: &Vec<Download>) -> HashMap<u64, (String, String, f
     items
 }
 
-fn download_to_values(dl: &Download) -> Option<(u64, (String, String, f32, String, String))> {
+fn download_to_values(dl: &Download) -> Option<(u64, DownloadRow)> {
     match dl.download_info() {
         &Some(ref download_info) => {
             let dlid = dl.id();
-            let name = dl.name().to_owned();
-            let size = (download_info.total() as f32).convert_to_byte_units(0);
             let percent = download_info.percentage();
-            // actual gtk amount is out of 100.0
-            let actual_gtk_amount = percent * 100.0;
-            let speed = format!("{}/s", download_info.speed().convert_to_byte_units(0));
-            let eta = download_info.eta();
-            Some((dlid, (name, size, actual_gtk_amount, speed, eta)))
+            let speed = download_info.speed();
+            Some((dlid,
+                  DownloadRow {
+                name: dl.name().to_owned(),
+                size: (download_info.total() as f32).convert_to_byte_units(0),
+                // actual gtk amount is out of 100.0
+                progress: percent * 100.0,
+                speed: format!("{}/s", speed.convert_to_byte_units(0)),
+                eta: download_info.eta(),
+                history: sparkline(download_info.speed_history().iter()),
+                id: dlid,
+                raw_size: download_info.total() as u64,
+                raw_speed: speed,
+                raw_eta: download_info.eta_seconds().unwrap_or(UNKNOWN_ETA),
+                state: download_info.state(),
+            }))
         }
         &None => None,
     }
 }
+
+pub fn summary_to_string(summary: &QueueSummary) -> String {
+    let eta = match summary.eta {
+        Some(eta) => format_duration(eta),
+        None => "N/A".to_owned(),
+    };
+    format!("{}/s  |  {} active, {} queued, {} paused, {} done, {} failed  |  {} of {}  |  ETA {}",
+            summary.speed.convert_to_byte_units(1),
+            summary.active,
+            summary.queued,
+            summary.paused,
+            summary.done,
+            summary.failed,
+            (summary.downloaded as f32).convert_to_byte_units(1),
+            (summary.known_total as f32).convert_to_byte_units(1),
+            eta)
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::fs::create_dir_all;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Sender, Receiver};
+use time::precise_time_s;
+use args::{Args, category_matches};
+use catalog::{self, Catalog, ChangeKind};
+use data::*;
+use gui::summary_to_string;
+use helper::Ignore;
+use refresh::{self, Refresher, spawn_refresher, subscribed};
+use config;
+
+// seconds between progress lines
+const PROGRESS_INTERVAL: f64 = 5.0;
+
+// Downloads without the gui: queues --enable, the unfinished downloads of the last run and
+// new books in subscribed categories, then prints progress until the queue is done. With
+// a refresh interval it keeps running and queues what the refreshes find. Returns the exit
+// status, 1 if anything failed.
+pub fn headless(data: &[Category],
+                update_recv: Receiver<GuiUpdateMsg>,
+                command_send: Sender<GuiCmdMsg>,
+                args: &Args,
+                catalog: Catalog)
+                -> i32 {
+    let download_dir = args.dir.clone().unwrap_or(config::download_dir());
+    if let Err(e) = create_dir_all(&download_dir) {
+        error!("Failed to create download dir {:?}: {}", download_dir, e);
+        return 1;
+    }
+    let restored = if config::startup().restore_selection {
+        catalog.previous_selection().into_iter().collect()
+    } else {
+        HashSet::new()
+    };
+    let new_ids = catalog.changes(data)
+                         .into_iter()
+                         .filter(|change| change.kind == ChangeKind::Added)
+                         .map(|change| change.id)
+                         .collect::<HashSet<u64>>();
+    let subscriptions = refresh::subscriptions(&args.subscribe);
+    let mut queued = 0;
+    for category in data.iter() {
+        let enabled = args.enable.iter().any(|name| category_matches(category.name(), name));
+        for download in category.downloads() {
+            if enabled || restored.contains(&download.id()) ||
+               (new_ids.contains(&download.id()) && subscribed(category.name(), &subscriptions)) {
+                command_send.send(GuiCmdMsg::Add(download.id(), download_dir.clone())).ignore();
+                queued += 1;
+            }
+        }
+    }
+
+    let refreshing = match refresh::list_source(args) {
+        Some(source) => {
+            let refresher = Refresher::new(source, data, args);
+            let refreshing = refresher.refresh_minutes() > 0;
+            spawn_refresher(Arc::new(Mutex::new(refresher)),
+                            command_send.clone(),
+                            Arc::new(Mutex::new(download_dir.clone())));
+            refreshing
+        }
+        None => false,
+    };
+    let (downloads, succeeded) = if queued == 0 && !refreshing {
+        println!("Nothing to download, pick categories with --enable or --subscribe");
+        (Vec::new(), true)
+    } else {
+        println!("Downloading {} books into {:?}", queued, download_dir);
+        run(&update_recv, &command_send, queued, refreshing)
+    };
+
+    command_send.send(GuiCmdMsg::Stop).ignore();
+    let mut categories = Vec::new();
+    for name in downloads.iter().filter_map(|download| download.category_name()) {
+        if !categories.contains(&name) {
+            categories.push(name);
+        }
+    }
+    let unfinished = downloads.iter()
+                              .filter(|download| !download.finished())
+                              .map(|download| download.id())
+                              .collect();
+    let snapshot = catalog.snapshot(data, &downloads, unfinished, categories);
+    if let Err(e) = catalog::save_snapshot(&snapshot) {
+        error!("{}", e);
+    }
+    if succeeded {
+        0
+    } else {
+        1
+    }
+}
+
+// mirrors the download list from the comm handler's updates like the gui does, returns it
+// once the queue is done along with whether nothing failed
+fn run(update_recv: &Receiver<GuiUpdateMsg>,
+       command_send: &Sender<GuiCmdMsg>,
+       queued: usize,
+       refreshing: bool)
+       -> (Vec<Download>, bool) {
+    let mut downloads: Vec<Download> = Vec::new();
+    let mut summary = QueueSummary::default();
+    let mut next_progress = precise_time_s() + PROGRESS_INTERVAL;
+    while let Ok(changes) = update_recv.recv() {
+        for change in changes {
+            match change {
+                GuiChange::Add(download) => downloads.push(download),
+                GuiChange::Set(idx, download) => {
+                    if idx < downloads.len() {
+                        let state = download.state();
+                        if state != downloads[idx].state() {
+                            match state {
+                                Some(DownloadState::Finished) => {
+                                    println!("Finished {}", download.name())
+                                }
+                                Some(DownloadState::Failed) => {
+                                    let error = match download.download_info() {
+                                        &Some(ref info) => {
+                                            info.errors()
+                                                .last()
+                                                .map(|&(_, ref e)| e.clone())
+                                                .unwrap_or(String::new())
+                                        }
+                                        &None => String::new(),
+                                    };
+                                    warn!("Failed {}: {}", download.name(), error)
+                                }
+                                _ => {}
+                            }
+                        }
+                        downloads[idx] = download;
+                    }
+                }
+                GuiChange::Remove(idx) => {
+                    if idx < downloads.len() {
+                        downloads.remove(idx);
+                    }
+                }
+                // nobody to ask, numbered like the default policy
+                GuiChange::Collision(id, question) => {
+                    println!("{} Adding a number.", question);
+                    command_send.send(GuiCmdMsg::ResolveCollision(id, CollisionPolicy::Suffix))
+                                .ignore();
+                }
+                GuiChange::Notice(message) => println!("{}", message),
+                GuiChange::Panicked(true, error) => error!("{}", error),
+                GuiChange::Panicked(false, error) => {
+                    error!("Communication handler panicked: {}", error);
+                    return (downloads, false);
+                }
+                GuiChange::Summary(new_summary) => summary = new_summary,
+                GuiChange::Open(_) | GuiChange::OpenFile(_) | GuiChange::Extend(_) => {}
+            }
+        }
+        if precise_time_s() >= next_progress {
+            println!("{}", summary_to_string(&summary));
+            next_progress = precise_time_s() + PROGRESS_INTERVAL;
+        }
+        // every add has been listed and nothing is left to do
+        if !refreshing && downloads.len() >= queued && summary.queued + summary.active == 0 {
+            println!("{} downloaded, {} failed", summary.done, summary.failed);
+            return (downloads, summary.failed == 0);
+        }
+    }
+    (downloads, summary.failed == 0)
+}
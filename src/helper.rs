@@ -1,5 +1,11 @@
 use std::iter;
-use std::char;
+use std::io;
+use std::io::prelude::*;
+use std::fs::{File, rename};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use time;
+use time::Timespec;
 
 pub fn make_string_if_nonzero(n: i64, id: &'static str) -> String {
     if n != 0 {
@@ -9,6 +15,27 @@ pub fn make_string_if_nonzero(n: i64, id: &'static str) -> String {
     }
 }
 
+const DURATION_UNITS: [(u64, &'static str); 4] = [(604800, "w"),
+(86400, "d"),
+(3600, "h"),
+(60, "m")];
+
+// seconds -> "1h 2m 3s", each unit only holding what the larger ones don't
+pub fn format_duration(seconds: f64) -> String {
+    let mut remaining = maximum(seconds.round(), 0.0) as u64;
+    let mut formatted = String::new();
+    for &(unit_seconds, id) in DURATION_UNITS.iter() {
+        formatted.push_str(&make_string_if_nonzero((remaining / unit_seconds) as i64, id));
+        remaining %= unit_seconds;
+    }
+    format!("{}{}s", formatted, remaining)
+}
+
+// local wall clock time
+pub fn format_timestamp(timestamp: Timespec) -> String {
+    time::strftime("%Y-%m-%d %H:%M:%S", &time::at(timestamp)).unwrap_or(String::new())
+}
+
 pub trait Ignore {
     fn ignore(&self);
 }
@@ -45,6 +72,22 @@ impl ToByteUnits for f32 {
     }
 }
 
+const SPARK_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// one bar per value, scaled to the largest
+pub fn sparkline<'a, I: Iterator<Item = &'a f32> + Clone>(values: I) -> String {
+    let max = values.clone().fold(0.0f32, |acc, &v| maximum(acc, v));
+    values.map(|&v| {
+              if max <= 0.0 {
+                  SPARK_BARS[0]
+              } else {
+                  let idx = (v / max * (SPARK_BARS.len() - 1) as f32).round() as usize;
+                  SPARK_BARS[minimum(idx, SPARK_BARS.len() - 1)]
+              }
+          })
+          .collect()
+}
+
 pub trait Repetition {
     fn repeat(&self, times: usize) -> String;
 }
@@ -76,35 +119,110 @@ pub fn round_to_places(n: f32, places: usize) -> f32 {
     (n * div).round() / div
 }
 
-pub fn name_to_fname(s: &str) -> String {
-    spaces_to_underscores(s) + ".pdf"
-}
-
-pub fn name_to_dname(s: &str) -> String {
-    let s = spaces_to_underscores(&remove_leading_spaces(s));
-    let first_non_period = s.find(char::is_not_period).unwrap();
-    return s[first_non_period..s.len()].to_owned();
+// "http://www.example.com:80/a.pdf" -> "www.example.com"
+pub fn url_host(url: &str) -> Option<&str> {
+    let after_scheme = match url.find("://") {
+        Some(idx) => &url[idx + 3..],
+        None => return None,
+    };
+    let end = after_scheme.find(|c| c == '/' || c == ':' || c == '?' || c == '#')
+                          .unwrap_or(after_scheme.len());
+    let host = &after_scheme[..end];
+    // drop any user info
+    let host = match host.rfind('@') {
+        Some(idx) => &host[idx + 1..],
+        None => host,
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
 }
 
-fn spaces_to_underscores(s: &str) -> String {
-    s.replace(" ", "_")
-}
+// the same link written differently compares equal, e.g.
+// "HTTP://Example.com:80/a.pdf#page=2" -> "http://example.com/a.pdf"
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    let url = url.split('#').next().unwrap_or(url);
+    let (scheme, rest) = match url.find("://") {
+        Some(idx) => (url[..idx].to_lowercase(), &url[idx + 3..]),
+        None => return url.to_owned(),
+    };
+    let host_end = rest.find(|c| c == '/' || c == '?').unwrap_or(rest.len());
+    let mut host = rest[..host_end].to_lowercase();
+    let default_port = match &scheme as &str {
+        "http" => ":80",
+        "https" => ":443",
+        _ => "",
+    };
+    if !default_port.is_empty() && host.ends_with(default_port) {
+        let port_start = host.len() - default_port.len();
+        host.truncate(port_start);
+    }
+    let path = match &rest[host_end..] {
+        "" => "/",
+        path => path,
+    };
+    format!("{}://{}{}", scheme, host, path)
+}
+
+// where a download is written to until it completes
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or(OsStr::new("download")).to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// write the whole file beside the old one and swap it in, so a crash mid-write
+// leaves the previous version intact
+pub fn write_replacing(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path(path);
+    try!(File::create(&tmp).and_then(|mut f| {
+        try!(f.write_all(contents));
+        f.sync_all()
+    }));
+    rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_url, format_duration};
+
+    #[test]
+    fn lowercases_scheme_and_host() {
+        assert_eq!(normalize_url("HTTP://Example.COM/Book.pdf"),
+                   "http://example.com/Book.pdf");
+    }
 
-fn remove_leading_spaces(s: &str) -> String {
-    let first_non_whitespace = s.find(char::is_not_whitespace).unwrap();
-    return s[first_non_whitespace..s.len()].to_owned();
-}
+    #[test]
+    fn drops_default_ports_and_fragments() {
+        assert_eq!(normalize_url("http://example.com:80/a.pdf#page=2"),
+                   "http://example.com/a.pdf");
+        assert_eq!(normalize_url("https://example.com:443/a.pdf"),
+                   "https://example.com/a.pdf");
+        assert_eq!(normalize_url("https://example.com:8443/a.pdf"),
+                   "https://example.com:8443/a.pdf");
+    }
 
-trait NotChar {
-    fn is_not_whitespace(self) -> bool;
-    fn is_not_period(self) -> bool;
-}
+    #[test]
+    fn adds_the_root_path() {
+        assert_eq!(normalize_url(" http://example.com "), "http://example.com/");
+    }
 
-impl NotChar for char {
-    fn is_not_whitespace(self) -> bool {
-        !self.is_whitespace()
+    #[test]
+    fn leaves_other_strings_alone() {
+        assert_eq!(normalize_url("not a url"), "not a url");
     }
-    fn is_not_period(self) -> bool {
-        self != '.'
+
+    #[test]
+    fn rolls_durations_over_into_larger_units() {
+        assert_eq!(format_duration(59.0), "59s");
+        assert_eq!(format_duration(59.6), "1m 0s");
+        assert_eq!(format_duration(60.0), "1m 0s");
+        assert_eq!(format_duration(3599.0), "59m 59s");
+        assert_eq!(format_duration(3600.0), "1h 0s");
+        assert_eq!(format_duration(90061.0), "1d 1h 1m 1s");
+        assert_eq!(format_duration(-5.0), "0s");
     }
 }
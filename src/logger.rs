@@ -0,0 +1,223 @@
+use std::env;
+use std::io::prelude::*;
+use std::io::stderr;
+use std::fs::{File, OpenOptions, create_dir_all, rename, remove_file};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use log;
+use log::{LogRecord, LogLevelFilter, LogMetadata, MaxLogLevelFilter};
+use time;
+use helper::{Ignore, maximum};
+use constants::{LOG_ENV_VAR, LOG_FILE_NAME, LOG_MAX_BYTES, LOG_MAX_FILES};
+
+// prefix of every target produced by the log macros in this crate
+const CRATE_TARGET_PREFIX: &'static str = "pbook_gui::";
+
+lazy_static! {
+    static ref FILTER: Arc<RwLock<Filter>> = Arc::new(RwLock::new(Filter::new(LogLevelFilter::Info)));
+    static ref MAX_LEVEL: Mutex<Option<MaxLogLevelFilter>> = Mutex::new(None);
+    // records echoed to stderr, the log file gets everything the filter lets through
+    static ref CONSOLE_LEVEL: RwLock<LogLevelFilter> = RwLock::new(LogLevelFilter::Info);
+}
+
+// Sets up the global logger.
+// Verbosity is read from PBOOK_LOG, e.g. "debug" or "info,downloader=trace,theme=warn".
+pub fn init(log_dir: &Path) -> Result<(), String> {
+    if let Ok(spec) = env::var(LOG_ENV_VAR) {
+        *FILTER.write().unwrap() = Filter::parse(&spec);
+    }
+    let file = RotatingFile::open(log_dir.join(LOG_FILE_NAME), LOG_MAX_BYTES, LOG_MAX_FILES);
+    let file_error = file.as_ref().err().cloned();
+    let result = log::set_logger(|max_level| {
+        max_level.set(FILTER.read().unwrap().max());
+        *MAX_LEVEL.lock().unwrap() = Some(max_level);
+        Box::new(Logger { file: Mutex::new(file.ok()) })
+    });
+    if let Err(e) = result {
+        return Err(format!("Failed to set logger: {}", e));
+    }
+    if let Some(e) = file_error {
+        warn!("Logging to console only: {}", e);
+    }
+    Ok(())
+}
+
+// raise or lower the default verbosity while running, keeping per-module overrides
+pub fn set_level(level: LogLevelFilter) {
+    let mut filter = FILTER.write().unwrap();
+    filter.default = level;
+    if let Some(ref max_level) = *MAX_LEVEL.lock().unwrap() {
+        max_level.set(filter.max());
+    }
+}
+
+// --quiet and --verbose, stdout is left to the program's own output either way
+pub fn set_console_level(level: LogLevelFilter) {
+    *CONSOLE_LEVEL.write().unwrap() = level;
+}
+
+pub fn level() -> LogLevelFilter {
+    FILTER.read().unwrap().default
+}
+
+struct Filter {
+    default: LogLevelFilter,
+    // (module, level), e.g. ("downloader", Trace)
+    modules: Vec<(String, LogLevelFilter)>,
+}
+
+impl Filter {
+    fn new(default: LogLevelFilter) -> Filter {
+        Filter {
+            default: default,
+            modules: Vec::new(),
+        }
+    }
+
+    fn parse(spec: &str) -> Filter {
+        let mut filter = Filter::new(LogLevelFilter::Info);
+        for directive in spec.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let first = parts.next().unwrap_or("");
+            match parts.next() {
+                Some(level) => {
+                    if let Ok(level) = LogLevelFilter::from_str(level.trim()) {
+                        filter.modules.push((first.trim().to_owned(), level));
+                    }
+                }
+                None => {
+                    if let Ok(level) = LogLevelFilter::from_str(first) {
+                        filter.default = level;
+                    } else {
+                        // a bare module name turns on everything for it
+                        filter.modules.push((first.to_owned(), LogLevelFilter::Trace));
+                    }
+                }
+            }
+        }
+        filter
+    }
+
+    fn level_for(&self, target: &str) -> LogLevelFilter {
+        let module = module_name(target);
+        // longest matching module wins
+        let mut best: Option<&(String, LogLevelFilter)> = None;
+        for directive in self.modules.iter() {
+            if module == directive.0 || module.starts_with(&format!("{}::", directive.0)) {
+                if best.map(|b| b.0.len() < directive.0.len()).unwrap_or(true) {
+                    best = Some(directive);
+                }
+            }
+        }
+        best.map(|b| b.1).unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LogLevelFilter {
+        self.modules.iter().fold(self.default, |acc, m| maximum(acc, m.1))
+    }
+}
+
+fn module_name(target: &str) -> &str {
+    if target.starts_with(CRATE_TARGET_PREFIX) {
+        &target[CRATE_TARGET_PREFIX.len()..]
+    } else {
+        target
+    }
+}
+
+struct Logger {
+    file: Mutex<Option<RotatingFile>>,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= FILTER.read().unwrap().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = time::strftime("%Y-%m-%d %H:%M:%S", &time::now()).unwrap_or(String::new());
+        let line = format!("{} {:<5} [{}] {}",
+                           timestamp,
+                           record.level(),
+                           module_name(record.target()),
+                           record.args());
+        if record.level() <= *CONSOLE_LEVEL.read().unwrap() {
+            writeln!(stderr(), "{}", line).ignore();
+        }
+        if let Some(ref mut file) = *self.file.lock().unwrap() {
+            file.write_line(&line).ignore();
+        }
+    }
+}
+
+// log file that gets moved to .1, .2, ... once it grows past max_bytes
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<RotatingFile, String> {
+        if let Some(parent) = path.parent() {
+            if let Err(e) = create_dir_all(parent) {
+                return Err(format!("Failed to create log dir {:?}: {}", parent, e));
+            }
+        }
+        let file = try!(open_append(&path));
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(RotatingFile {
+            path: path,
+            file: file,
+            size: size,
+            max_bytes: max_bytes,
+            max_files: max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        if self.size + line.len() as u64 + 1 > self.max_bytes {
+            try!(self.rotate());
+        }
+        match writeln!(self.file, "{}", line) {
+            Ok(_) => {
+                self.size += line.len() as u64 + 1;
+                Ok(())
+            }
+            Err(e) => Err(format!("{}", e)),
+        }
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        self.file.flush().ignore();
+        // drop the oldest, then shift the rest up by one
+        remove_file(self.numbered(self.max_files)).ignore();
+        for n in (1..self.max_files).rev() {
+            rename(self.numbered(n), self.numbered(n + 1)).ignore();
+        }
+        rename(&self.path, self.numbered(1)).ignore();
+        self.file = try!(open_append(&self.path));
+        self.size = 0;
+        Ok(())
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = self.path.file_name().unwrap().to_os_string();
+        name.push(format!(".{}", n));
+        self.path.with_file_name(name)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open log file {:?}: {}", path, e))
+}
@@ -10,13 +10,23 @@ extern crate gobject_sys;
 extern crate pango_sys;
 extern crate gdk_pixbuf;
 extern crate gio_sys;
+extern crate gdk_sys;
 extern crate libc;
+extern crate rustc_serialize;
+extern crate unicode_normalization;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate log;
 
+use std::env;
+use std::io::prelude::*;
+use std::io::stderr;
+use std::process;
 use std::thread;
 use std::time::Duration;
 use std::sync::mpsc::channel;
+use log::LogLevelFilter;
 
 mod data;
 mod include;
@@ -32,14 +42,107 @@ mod constants;
 mod fsthread;
 mod button;
 mod menu;
+mod paths;
+mod logger;
+mod rate;
+mod details;
+mod config;
+mod preferences;
+mod args;
+mod pathtemplate;
+mod sanitize;
+mod catalog;
+mod changes;
+mod refresh;
+mod headless;
+mod diagnostics;
+mod skipped;
+mod sources;
 
 use commhandler::*;
 use parse::*;
 use include::RAW_DATA;
+use args::parse_args;
+use catalog::Catalog;
+use helper::{Ignore, maximum};
 
 fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}\n\n{}", e, args::usage());
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", args::usage());
+        return;
+    }
+    if args.version {
+        println!("pbook-gui {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    if let Some(ref config_path) = args.config {
+        config::set_config_path(config_path.clone());
+    }
+    if let Some(ref format) = args.format {
+        if sources::find(format).is_none() {
+            println!("Unknown list format \"{}\"\n\n{}", format, args::usage());
+            process::exit(2);
+        }
+    }
+
+    if let Err(e) = logger::init(&paths::log_dir()) {
+        writeln!(stderr(), "{}", e).ignore();
+    }
+    if args.verbose {
+        // PBOOK_LOG may already ask for more
+        logger::set_level(maximum(logger::level(), LogLevelFilter::Debug));
+        logger::set_console_level(LogLevelFilter::Debug);
+    } else if args.quiet {
+        logger::set_console_level(LogLevelFilter::Warn);
+    }
+    info!("Starting pbook-gui {}", env!("CARGO_PKG_VERSION"));
     let threads = num_cpus::get();
-    let mut parsed_data: Vec<Category> = parse(RAW_DATA);
+    let configured_threads = match (args.threads, config::startup().threads) {
+        (Some(n), _) => n,
+        (None, 0) => threads,
+        (None, n) => n,
+    };
+    let source = refresh::list_source(&args);
+    let (contents, list_format) = match source {
+        Some(ref source) => {
+            match read_list(source) {
+                Ok(contents) => (contents, sources::by_id(&sources::format_id(&args))),
+                // the configured list can be offline, one given for this session or linted
+                // has to work
+                Err(ref e) if args.list.is_none() && !args.lint => {
+                    warn!("{}, using the built in list", e);
+                    (RAW_DATA.to_owned(), sources::by_id(sources::DEFAULT_FORMAT))
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        None => (RAW_DATA.to_owned(), sources::by_id(sources::DEFAULT_FORMAT)),
+    };
+    info!("Reading the book list as {}", list_format.name());
+    let (mut parsed_data, diagnostics) = list_format.parse(&contents);
+    if args.lint {
+        let source = source.unwrap_or("built in list".to_owned());
+        process::exit(diagnostics::lint(&source, &parsed_data, &diagnostics, args.json));
+    }
+    if !diagnostics.entries().is_empty() {
+        info!("Skipped some of the list: {}", diagnostics::summarize(&diagnostics));
+    }
+    for category in args.enable.iter() {
+        if !parsed_data.iter().any(|c| args::category_matches(c.name(), category)) {
+            warn!("No category named \"{}\" in the list", category);
+        }
+    }
+    let catalog = Catalog::new(&parsed_data, catalog::load_snapshot());
     let downloadthreads_data = parsed_data.clone();
 
     // initialize the channels between gui and comm handler
@@ -47,7 +150,7 @@ fn main() {
     let (gui_cmd_send, gui_cmd_recv) = channel::<GuiCmdMsg>();
     let commhandler_channels = (gui_update_send, gui_cmd_recv);
 
-    let mut comm_handler = CommHandler::new(threads,
+    let mut comm_handler = CommHandler::new(configured_threads,
                                             downloadthreads_data.to_downloads(),
                                             // Vec::new(),
                                             commhandler_channels);
@@ -59,6 +162,20 @@ fn main() {
         }
     });
 
+    if args.headless {
+        process::exit(headless::headless(&parsed_data,
+                                         gui_update_recv,
+                                         gui_cmd_send,
+                                         &args,
+                                         catalog));
+    }
+
     // start gtk gui
-    gui::gui(&mut parsed_data, gui_update_recv, gui_cmd_send, threads);
+    gui::gui(&mut parsed_data,
+             gui_update_recv,
+             gui_cmd_send,
+             threads,
+             &args,
+             catalog,
+             diagnostics);
 }
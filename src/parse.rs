@@ -1,64 +1,195 @@
 pub use data::*;
 use std::ascii::AsciiExt;
+use std::io::prelude::*;
+use std::fs::File;
+use hyper::client::Client;
+use diagnostics::{Diagnostics, DiagnosticKind};
+use config;
 
-// pub fn parse(data: &str) -> Vec<Vec<Download>> {
-pub fn parse(data: &str) -> Vec<Category> {
-    let padded_data = blanks_to_newlines(data.split('\n')
-                                         .map(|l| l.trim().to_owned())
-                                         .collect::<Vec<String>>());
-    let category_data = padded_data.split("\n")
-        .map(|s| s.to_owned())
-        .collect::<Vec<String>>();
-    get_categories(remove_blanks(category_data), '#')
+// contents of a book list given as a path or an http(s) url
+pub fn read_list(source: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let client = Client::new();
+        let mut response = try!(client.get(source)
+                                      .send()
+                                      .map_err(|e| format!("Failed to fetch list {}: {}", source, e)));
+        if !response.status.is_success() {
+            return Err(format!("Failed to fetch list {}: {}", source, response.status));
+        }
+        try!(response.read_to_string(&mut contents)
+                     .map_err(|e| format!("Failed to read list {}: {}", source, e)));
+    } else {
+        try!(File::open(source)
+                 .and_then(|mut f| f.read_to_string(&mut contents))
+                 .map_err(|e| format!("Failed to read list {}: {}", source, e)));
+    }
+    Ok(contents)
+}
+
+// What a markdown list looks like, sources.rs has the ones for each kind of list
+#[derive(Debug, Clone)]
+pub struct ParseRules {
+    pub title_identifier: char,
+    // headings at this level are the top level categories, the ones above title the list
+    pub top_level: usize,
+    // headings that aren't categories, e.g. a table of contents, with everything under them
+    pub skip_headings: Vec<String>,
+    // only links in one of the accepted formats are books, otherwise every link is
+    pub formats_only: bool,
+    // "[title](url) - Author", awesome lists put a description there instead
+    pub authors: bool,
+}
+
+impl ParseRules {
+    pub fn free_programming_books() -> ParseRules {
+        ParseRules {
+            title_identifier: '#',
+            top_level: 2,
+            skip_headings: vec!["Index".to_owned()],
+            formats_only: true,
+            authors: true,
+        }
+    }
 }
 
-pub fn get_categories(vec_data: Vec<String>, title_identifier: char) -> Vec<Category> {
+// the categories and what was left out of them
+pub fn parse_markdown(data: &str, rules: &ParseRules) -> (Vec<Category>, Diagnostics) {
+    // (line number, line) of the lines that aren't blank
+    let lines = data.lines()
+                    .enumerate()
+                    .map(|(idx, line)| (idx + 1, line.trim().to_owned()))
+                    .filter(|&(_, ref line)| !line.is_empty())
+                    .collect::<Vec<(usize, String)>>();
+    let mut diagnostics = Diagnostics::new();
+    let categories = get_categories(lines, rules, &mut diagnostics);
+    diagnostics.sort();
+    (categories, diagnostics)
+}
+
+// Every heading from the top level down is a category, nested under the closest heading
+// above it with a lower level. Books above the first one aren't in a category.
+pub fn get_categories(vec_data: Vec<(usize, String)>,
+                      rules: &ParseRules,
+                      diagnostics: &mut Diagnostics)
+                      -> Vec<Category> {
     let mut categories: Vec<Category> = Vec::with_capacity(vec_data.len());
-    let mut category_name: String = "Index".to_owned();
-    let mut category: Category = Category::new(category_name, vec![]);
+    // (line number, line) of each category's heading
+    let mut heading_lines: Vec<(usize, String)> = Vec::with_capacity(vec_data.len());
+    // (level, title) of the current heading and the ones it's nested in
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut current: Option<Category> = None;
     let mut titles: Vec<String> = Vec::with_capacity(vec_data.len());
-    for entry in vec_data {
-        let title_head = [title_identifier; 3]
-        .into_iter()
-        .map(|&c| c.clone())
-        .collect::<String>() + " ";
-
-        if entry.contains(&title_head) {
-            categories.push(category.clone());
-            category_name = get_title_name('#', entry);
+    let accepted_formats = config::startup()
+                               .accepted_formats
+                               .iter()
+                               .map(|format| format.to_ascii_lowercase())
+                               .collect::<Vec<String>>();
+    for (line, entry) in vec_data {
+        if let Some((level, title)) = get_heading(rules.title_identifier, &entry) {
+            if let Some(category) = current.take() {
+                categories.push(category);
+            }
             titles.clear();
-            category = Category::new(category_name, vec![])
-        } else {
-            match get_item_info(entry) {
+            // the title of the whole list
+            if level < rules.top_level {
+                headings.clear();
+                continue;
+            }
+            while headings.last().map(|&(parent_level, _)| parent_level >= level).unwrap_or(false) {
+                headings.pop();
+            }
+            headings.push((level, title));
+            let path = headings.iter().map(|&(_, ref title)| title.clone()).collect();
+            current = Some(Category::new(path, vec![]));
+            heading_lines.push((line, entry.clone()));
+        } else if let Some(ref mut category) = current {
+            let report = !is_skipped(category, rules);
+            match get_item_info(entry.clone()) {
                 Some(data) => {
                     // data.0 is title
                     let preexisting_titlecount = titles.count_item(&data.0);
                     titles.push(data.0.clone());
                     // data.1 is url
-                    if data.1.to_ascii_lowercase().contains("pdf") {
-                        let dl;
+                    let lowercase_url = data.1.to_ascii_lowercase();
+                    if !rules.formats_only ||
+                       accepted_formats.iter().any(|format| lowercase_url.contains(format)) {
+                        let mut dl;
                         if preexisting_titlecount > 0 {
+                            if report {
+                                diagnostics.push(line,
+                                                 DiagnosticKind::DuplicateTitle,
+                                                 category.name(),
+                                                 &entry);
+                            }
                             dl = Download::new(&format!("{} {}", &data.0, preexisting_titlecount), &data.1, Some(category.name().to_owned()));
                         } else {
                             dl = Download::new(&data.0, &data.1, Some(category.name().to_owned()));
                         }
+                        if rules.authors {
+                            dl.set_author(data.2);
+                        }
                         category.add_download(dl);
+                    } else if report {
+                        diagnostics.push(line,
+                                         DiagnosticKind::FilteredFormat,
+                                         category.name(),
+                                         &entry);
                     }
                 }
+                // anything else is text between the books
+                None if report && is_list_item(&entry) => {
+                    diagnostics.push(line, DiagnosticKind::MalformedLink, category.name(), &entry)
+                }
                 None => {}
             }
         }
     }
 
-    // remove all unnecessary categories
-    categories = categories.iter()
-        .filter(|c| {
-            !c.name().to_ascii_lowercase().contains("index") &&
-                c.downloads().len() != 0
-        })
-    .map(|c| c.clone())
-        .collect();
-    categories
+    if let Some(category) = current.take() {
+        categories.push(category);
+    }
+    prune_categories(categories, &heading_lines, rules, diagnostics)
+}
+
+// Drops the skipped headings, e.g. an "Index" that only links to the others, and headings
+// without any books under them. Subcategories follow their parent, so a subtree is a run
+// of deeper ones.
+fn prune_categories(categories: Vec<Category>,
+                    heading_lines: &[(usize, String)],
+                    rules: &ParseRules,
+                    diagnostics: &mut Diagnostics)
+                    -> Vec<Category> {
+    let mut pruned = Vec::with_capacity(categories.len());
+    for (idx, category) in categories.iter().enumerate() {
+        let has_books = categories[subtree(&categories, idx)]
+                            .iter()
+                            .any(|c| !c.downloads().is_empty());
+        if is_skipped(category, rules) {
+            continue;
+        } else if has_books {
+            pruned.push(category.clone());
+        } else {
+            let (line, ref heading) = heading_lines[idx];
+            diagnostics.push(line, DiagnosticKind::EmptyCategory, category.name(), heading);
+        }
+    }
+    pruned
+}
+
+fn is_skipped(category: &Category, rules: &ParseRules) -> bool {
+    category.path()
+            .first()
+            .map(|title| {
+                rules.skip_headings.iter().any(|skipped| title.eq_ignore_ascii_case(skipped))
+            })
+            .unwrap_or(false)
+}
+
+// "* ...", "- ..." or anything with a markdown link in it
+fn is_list_item(line: &str) -> bool {
+    line.starts_with("* ") || line.starts_with("- ") || line.starts_with("+ ") ||
+    line.contains("](")
 }
 
 trait CountItem {
@@ -77,35 +208,23 @@ impl CountItem for Vec<String> {
     }
 }
 
-fn blanks_to_newlines(vec_data: Vec<String>) -> String {
-    vec_data.iter()
-        .map(|s| {
-            if s == &"".to_owned() {
-                "\n".to_owned()
-            } else {
-                (s.clone() + "\n")
-            }
-        })
-    .collect::<Vec<String>>()
-        .join("")
-}
-
-fn remove_blanks(vec_data: Vec<String>) -> Vec<String> {
-    vec_data.iter()
-        .filter(|&s| s != &"".to_owned())
-        .map(|s| s.clone())
-        .collect::<Vec<String>>()
-}
-
-// removes the identifiers (# in this case) from the category title
-fn get_title_name(title_identifier: char, raw_name: String) -> String {
-    raw_name.chars()
-        .filter(|&c| c != title_identifier)
-        .collect::<String>()
+// "### Title" -> (3, "Title"), closing #s are dropped and ones inside the title kept ("C#")
+fn get_heading(title_identifier: char, line: &str) -> Option<(usize, String)> {
+    let line = line.trim();
+    let level = line.chars().take_while(|&c| c == title_identifier).count();
+    if level == 0 {
+        return None;
+    }
+    let title = line[level..].trim().trim_right_matches(title_identifier).trim();
+    if title.is_empty() {
+        None
+    } else {
+        Some((level, title.to_owned()))
+    }
 }
 
-// returns (name, url)
-pub fn get_item_info(raw_item: String) -> Option<(String, String)> {
+// returns (name, url, author)
+pub fn get_item_info(raw_item: String) -> Option<(String, String, Option<String>)> {
     let title_start: usize;
     let title_end: usize;
     let title: &str;
@@ -117,12 +236,16 @@ pub fn get_item_info(raw_item: String) -> Option<(String, String)> {
     } else {
         return None;
     }
-    if let Some(end) = raw_item.find("]") {
-        title_end = end;
+    if let Some(end) = raw_item[title_start..].find("]") {
+        title_end = title_start + end;
     } else {
         return None;
     }
     title = &raw_item[title_start..title_end];
+    // "[title]" without "(url)" after it
+    if !raw_item[title_end + 1..].starts_with("(") {
+        return None;
+    }
 
     if let Some(end) = (&raw_item[(title_end + 2)..].to_owned()).find(")") {
         url_end = end;
@@ -132,5 +255,28 @@ pub fn get_item_info(raw_item: String) -> Option<(String, String)> {
 
     url = &raw_item[(title_end + 2)..][..url_end];
 
-    Some((title.to_owned(), url.to_owned()))
+    // "* [Title](url) - Author (PDF)"
+    let rest = &raw_item[(title_end + 2)..][(url_end + 1)..];
+    let author = get_author(rest);
+
+    Some((title.to_owned(), url.to_owned(), author))
+}
+
+fn get_author(rest: &str) -> Option<String> {
+    let rest = rest.trim();
+    if !rest.starts_with("- ") {
+        return None;
+    }
+    let author = &rest[2..];
+    // drop trailing notes such as "(PDF)"
+    let author = match author.find(" (") {
+        Some(end) => &author[..end],
+        None => author,
+    };
+    let author = author.trim();
+    if author.is_empty() {
+        None
+    } else {
+        Some(author.to_owned())
+    }
 }
@@ -0,0 +1,151 @@
+use std::env;
+use std::io::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use constants::{DATA_DIR_NAME, LOG_DIR_NAME, APP_DIR_NAME, DOWNLOADS_DIR_NAME, PORTABLE_MARKER};
+
+// directory that holds the running executable
+pub fn exe_dir() -> PathBuf {
+    match env::current_exe() {
+        Ok(exe_path) => exe_path.parent().unwrap_or(Path::new("..")).to_path_buf(),
+        Err(e) => {
+            warn!("Failed to get current exe path: {}", e);
+            Path::new(".").to_path_buf()
+        }
+    }
+}
+
+// root of the install, accounting for whether the exe is in a bin dir or not
+pub fn base_dir() -> PathBuf {
+    let exe_dir = exe_dir();
+    if exe_dir.file_name().map(|n| n == "bin").unwrap_or(false) {
+        exe_dir.parent().unwrap_or(Path::new("..")).to_path_buf()
+    } else {
+        exe_dir
+    }
+}
+
+// A marker file in the install root keeps everything next to the executable,
+// like a copy carried around on a usb stick. Otherwise the user's dirs are used.
+pub fn is_portable() -> bool {
+    base_dir().join(PORTABLE_MARKER).exists()
+}
+
+// everything the program writes in portable mode, and the fallback for the rest
+fn portable_dir() -> PathBuf {
+    base_dir().join(DATA_DIR_NAME)
+}
+
+// settings: config.json, the user's gtk.css and themes
+pub fn config_dir() -> PathBuf {
+    if is_portable() {
+        portable_dir()
+    } else {
+        config_home().map(|dir| dir.join(APP_DIR_NAME)).unwrap_or(portable_dir())
+    }
+}
+
+// things the user adds, like themes and snapshots of the book list
+pub fn data_dir() -> PathBuf {
+    if is_portable() {
+        portable_dir()
+    } else {
+        data_home().map(|dir| dir.join(APP_DIR_NAME)).unwrap_or(portable_dir())
+    }
+}
+
+// things worth keeping between runs that aren't worth backing up, like logs
+pub fn state_dir() -> PathBuf {
+    if is_portable() {
+        portable_dir()
+    } else {
+        state_home().map(|dir| dir.join(APP_DIR_NAME)).unwrap_or(portable_dir())
+    }
+}
+
+// where books go unless the user picked somewhere else
+pub fn default_download_dir() -> PathBuf {
+    if is_portable() {
+        base_dir().join(DOWNLOADS_DIR_NAME)
+    } else {
+        match download_home() {
+            Some(dir) => dir.join(APP_DIR_NAME),
+            None => base_dir().join(DOWNLOADS_DIR_NAME),
+        }
+    }
+}
+
+pub fn log_dir() -> PathBuf {
+    state_dir().join(LOG_DIR_NAME)
+}
+
+// $XDG_CONFIG_HOME or ~/.config, %APPDATA% on windows
+fn config_home() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        xdg_dir("XDG_CONFIG_HOME", ".config")
+    }
+}
+
+// $XDG_DATA_HOME or ~/.local/share, %LOCALAPPDATA% on windows
+fn data_home() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").or(env::var_os("APPDATA")).map(PathBuf::from)
+    } else {
+        xdg_dir("XDG_DATA_HOME", ".local/share")
+    }
+}
+
+// $XDG_STATE_HOME or ~/.local/state, %LOCALAPPDATA% on windows
+fn state_home() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").or(env::var_os("APPDATA")).map(PathBuf::from)
+    } else {
+        xdg_dir("XDG_STATE_HOME", ".local/state")
+    }
+}
+
+// $XDG_DOWNLOAD_DIR, else the one set in user-dirs.dirs, else ~/Downloads
+fn download_home() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DOWNLOAD_DIR").map(PathBuf::from) {
+        if dir.is_absolute() {
+            return Some(dir);
+        }
+    }
+    if !cfg!(windows) {
+        if let Some(dir) = config_home().and_then(|dir| read_user_dir(&dir.join("user-dirs.dirs"),
+                                                                      "XDG_DOWNLOAD_DIR")) {
+            return Some(dir);
+        }
+    }
+    env::home_dir().map(|home| home.join("Downloads"))
+}
+
+fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        // relative paths are invalid per the spec and get ignored
+        Some(ref dir) if Path::new(dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => env::home_dir().map(|home| home.join(home_fallback)),
+    }
+}
+
+// reads a line like XDG_DOWNLOAD_DIR="$HOME/Downloads" from user-dirs.dirs
+fn read_user_dir(user_dirs: &Path, var: &str) -> Option<PathBuf> {
+    let mut contents = String::new();
+    if File::open(user_dirs).and_then(|mut f| f.read_to_string(&mut contents)).is_err() {
+        return None;
+    }
+    let prefix = format!("{}=", var);
+    for line in contents.lines().map(|l| l.trim()).filter(|l| l.starts_with(&prefix)) {
+        let value = line[prefix.len()..].trim_matches('"');
+        if value.starts_with("$HOME") {
+            return env::home_dir().map(|home| {
+                home.join(value["$HOME".len()..].trim_left_matches('/'))
+            });
+        } else if Path::new(value).is_absolute() {
+            return Some(PathBuf::from(value));
+        }
+    }
+    None
+}
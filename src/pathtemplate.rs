@@ -0,0 +1,332 @@
+use std::ascii::AsciiExt;
+use std::path::{Path, PathBuf};
+use data::Download;
+use sanitize::sanitize_component;
+use helper::maximum;
+use config;
+
+pub const PLACEHOLDERS: [&'static str; 6] = ["language", "category", "author", "title", "ext",
+                                             "index"];
+
+// filled in for {author} when the list doesn't name one
+const UNKNOWN_AUTHOR: &'static str = "Unknown";
+
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum NameCase {
+    Keep,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum SpaceMode {
+    Keep,
+    Underscore,
+    Dash,
+    Remove,
+}
+
+// what happens when two different books resolve to the same file
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum CollisionPolicy {
+    // "Title (2).pdf"
+    Suffix,
+    Skip,
+    Overwrite,
+    Ask,
+}
+
+// (label, value) for the preferences dialog
+pub const COLLISION_POLICIES: [(&'static str, CollisionPolicy); 4] =
+    [("Add a number", CollisionPolicy::Suffix),
+     ("Skip the new book", CollisionPolicy::Skip),
+     ("Overwrite", CollisionPolicy::Overwrite),
+     ("Ask", CollisionPolicy::Ask)];
+
+pub const NAME_CASES: [(&'static str, NameCase); 3] = [("As listed", NameCase::Keep),
+                                                       ("lowercase", NameCase::Lower),
+                                                       ("UPPERCASE", NameCase::Upper)];
+
+pub const SPACE_MODES: [(&'static str, SpaceMode); 4] = [("Keep spaces", SpaceMode::Keep),
+                                                         ("Underscores", SpaceMode::Underscore),
+                                                         ("Dashes", SpaceMode::Dash),
+                                                         ("Remove", SpaceMode::Remove)];
+
+// Turns a book into a path relative to the download directory, e.g.
+// "{category}/{author} - {title}.{ext}" -> "Rust/Steve_Klabnik_-_The_Rust_Book.pdf",
+// a subcategory gets a folder inside its parent's
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTemplate {
+    template: String,
+    case: NameCase,
+    spaces: SpaceMode,
+    // characters per path component, 0 for no limit
+    max_length: usize,
+    language: String,
+    formats: Vec<String>,
+}
+
+impl PathTemplate {
+    pub fn from_config() -> PathTemplate {
+        config::with(|c| {
+            PathTemplate {
+                template: c.path_template.clone(),
+                case: c.path_case,
+                spaces: c.path_spaces,
+                max_length: c.path_max_length,
+                language: c.list_language.clone(),
+                formats: c.accepted_formats.clone(),
+            }
+        })
+    }
+
+    pub fn render(&self, download: &Download) -> PathBuf {
+        let filled = fill(&self.template, |placeholder| {
+            match placeholder {
+                "language" => Some(vec![self.language.clone()]),
+                "category" => Some(download.category_path().to_vec()),
+                "author" => Some(vec![download.author().unwrap_or(UNKNOWN_AUTHOR).to_owned()]),
+                "title" => Some(vec![download.name().to_owned()]),
+                "ext" => Some(vec![self.extension(download.url())]),
+                "index" => Some(vec![download.index().to_string()]),
+                _ => None,
+            }
+        });
+        let mut components = filled.split('/')
+                                   .map(|component| self.format_component(component))
+                                   .filter(|component| {
+                                       !component.chars().all(|c| c == '.' || c.is_whitespace())
+                                   })
+                                   .collect::<Vec<String>>();
+        if components.is_empty() {
+            components.push(self.format_component(download.name()));
+        }
+        if let Some(file_name) = components.pop() {
+            let ext_start = file_name.rfind('.').unwrap_or(file_name.len());
+            let (stem, ext) = file_name.split_at(ext_start);
+            // 0 is no limit, so a limit taken up by the extension still keeps a character
+            let stem_length = if self.max_length == 0 {
+                0
+            } else {
+                maximum(self.max_length.saturating_sub(ext.chars().count()), 1)
+            };
+            components.push(format!("{}{}", truncate(stem, stem_length), ext));
+        }
+        let mut path = PathBuf::new();
+        for component in components.iter() {
+            path.push(sanitize_component(component));
+        }
+        path
+    }
+
+    // directories keep the whole length, the file name is truncated in render,
+    // anything a filesystem would reject is left for sanitize_component
+    fn format_component(&self, component: &str) -> String {
+        let component = component.trim();
+        let component = match self.spaces {
+            SpaceMode::Keep => component.to_owned(),
+            SpaceMode::Underscore => component.replace(" ", "_"),
+            SpaceMode::Dash => component.replace(" ", "-"),
+            SpaceMode::Remove => component.replace(" ", ""),
+        };
+        let component = match self.case {
+            NameCase::Keep => component,
+            NameCase::Lower => component.to_lowercase(),
+            NameCase::Upper => component.to_uppercase(),
+        };
+        component
+    }
+
+    // extension of the url if it's an accepted format, else the first accepted format it mentions
+    fn extension(&self, url: &str) -> String {
+        let lowercase_url = url.to_ascii_lowercase();
+        let url_path = lowercase_url.split(|c| c == '?' || c == '#').next().unwrap_or("");
+        let url_ext = url_path.rsplit('/').next().and_then(|file_name| {
+            file_name.rfind('.').map(|idx| &file_name[idx + 1..])
+        });
+        if let Some(url_ext) = url_ext {
+            if self.formats.iter().any(|format| format.eq_ignore_ascii_case(url_ext)) {
+                return url_ext.to_owned();
+            }
+        }
+        self.formats
+            .iter()
+            .find(|format| lowercase_url.contains(&format.to_ascii_lowercase()))
+            .map(|format| format.to_ascii_lowercase())
+            .unwrap_or("pdf".to_owned())
+    }
+}
+
+// "Rust/Title.pdf", 2 -> "Rust/Title (2).pdf"
+pub fn with_suffix(relative_path: &Path, n: usize) -> PathBuf {
+    let stem = relative_path.file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                            .unwrap_or(String::new());
+    let file_name = match relative_path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    relative_path.with_file_name(file_name)
+}
+
+// two paths name the same file on a case insensitive filesystem
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+// a template has to name the book and only use known placeholders
+pub fn validate(template: &str) -> Result<(), String> {
+    let mut unknown = None;
+    let mut has_title = false;
+    fill(template, |placeholder| {
+        if placeholder == "title" {
+            has_title = true;
+        } else if !PLACEHOLDERS.iter().any(|&known| known == placeholder) && unknown.is_none() {
+            unknown = Some(placeholder.to_owned());
+        }
+        Some(Vec::new())
+    });
+    if let Some(placeholder) = unknown {
+        Err(format!("Unknown placeholder {{{}}}", placeholder))
+    } else if !has_title {
+        Err("The template has to contain {title}".to_owned())
+    } else if template.trim_right().ends_with('/') {
+        Err("The template has to end with a file name".to_owned())
+    } else {
+        Ok(())
+    }
+}
+
+// Replaces each {placeholder}. Slashes in a value become dashes so it can't add folders of
+// its own, a value in several parts gets one folder per part.
+fn fill<F>(template: &str, mut value: F) -> String
+    where F: FnMut(&str) -> Option<Vec<String>>
+{
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        filled.push_str(&rest[..open]);
+        let after_open = &rest[open + 1..];
+        match after_open.find('}') {
+            Some(close) => {
+                let placeholder = &after_open[..close];
+                match value(placeholder) {
+                    Some(parts) => {
+                        let parts = parts.iter()
+                                         .map(|part| part.replace("/", "-").replace("\\", "-"))
+                                         .collect::<Vec<String>>();
+                        filled.push_str(&parts.join("/"))
+                    }
+                    None => filled.push_str(&rest[open..open + close + 2]),
+                }
+                rest = &after_open[close + 1..];
+            }
+            None => {
+                filled.push_str(&rest[open..]);
+                rest = "";
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+// cut to a number of characters, 0 for no limit
+fn truncate(s: &str, max_length: usize) -> String {
+    if max_length == 0 {
+        s.to_owned()
+    } else {
+        s.chars().take(max_length).collect::<String>().trim_right().to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use data::Download;
+    use super::{PathTemplate, NameCase, SpaceMode, fill, truncate, validate, with_suffix};
+
+    fn template(template: &str, max_length: usize) -> PathTemplate {
+        PathTemplate {
+            template: template.to_owned(),
+            case: NameCase::Keep,
+            spaces: SpaceMode::Keep,
+            max_length: max_length,
+            language: "English".to_owned(),
+            formats: vec!["pdf".to_owned(), "epub".to_owned()],
+        }
+    }
+
+    fn book(name: &str, url: &str, category: &str) -> Download {
+        Download::new(name, url, Some(category.to_owned()))
+    }
+
+    #[test]
+    fn fills_known_placeholders() {
+        let filled = fill("{a}/{b} - {unknown}", |placeholder| {
+            match placeholder {
+                "a" => Some(vec!["x".to_owned(), "y".to_owned()]),
+                "b" => Some(vec!["AC/DC".to_owned()]),
+                _ => None,
+            }
+        });
+        assert_eq!(filled, "x/y/AC-DC - {unknown}");
+        assert_eq!(fill("{title", |_| Some(vec!["x".to_owned()])), "{title");
+    }
+
+    #[test]
+    fn renders_nested_categories_as_folders() {
+        let download = book("Intro", "http://a.com/intro.pdf", "Language Agnostic / Algorithms");
+        assert_eq!(template("{category}/{title}.{ext}", 0).render(&download),
+                   Path::new("Language Agnostic").join("Algorithms").join("Intro.pdf"));
+    }
+
+    #[test]
+    fn renders_formatted_components() {
+        let mut path_template = template("{language}/{author} - {title}.{ext}", 0);
+        path_template.case = NameCase::Lower;
+        path_template.spaces = SpaceMode::Underscore;
+        let download = book("The Rust Book", "http://a.com/get?format=EPUB", "Rust");
+        assert_eq!(path_template.render(&download),
+                   Path::new("english").join("unknown_-_the_rust_book.epub"));
+    }
+
+    #[test]
+    fn takes_the_extension_after_the_last_dot() {
+        let download = book("Node.js in Action", "http://a.com/node.pdf", "JavaScript");
+        assert_eq!(template("{title}.{ext}", 0).render(&download),
+                   PathBuf::from("Node.js in Action.pdf"));
+        assert_eq!(template("{title}.{ext}", 8).render(&download),
+                   PathBuf::from("Node.pdf"));
+    }
+
+    #[test]
+    fn keeps_a_stem_character() {
+        let download = book("Title", "http://a.com/title.pdf", "Rust");
+        assert_eq!(template("{title}.{ext}", 3).render(&download), PathBuf::from("T.pdf"));
+        assert_eq!(template("{title}.{ext}", 1).render(&download), PathBuf::from("T.pdf"));
+    }
+
+    #[test]
+    fn truncates_by_characters() {
+        assert_eq!(truncate("\u{e9}t\u{e9}", 2), "\u{e9}t");
+        assert_eq!(truncate("ab cd", 3), "ab");
+        assert_eq!(truncate("abc", 0), "abc");
+    }
+
+    #[test]
+    fn validates_templates() {
+        assert_eq!(validate("{category}/{title}.{ext}"), Ok(()));
+        assert!(validate("{category}/{author}.{ext}").is_err());
+        assert!(validate("{title} {year}").is_err());
+        assert!(validate("{title}/").is_err());
+    }
+
+    #[test]
+    fn suffixes_the_stem() {
+        assert_eq!(with_suffix(Path::new("Rust/Node.js in Action.pdf"), 2),
+                   Path::new("Rust").join("Node.js in Action (2).pdf"));
+        assert_eq!(with_suffix(Path::new("Rust/README"), 3),
+                   Path::new("Rust").join("README (3)"));
+    }
+}
@@ -0,0 +1,272 @@
+use gtk;
+use gtk::prelude::*;
+use gtk::IsA;
+use config::Config;
+use constants::AUTO_GTK_THEME;
+use fsthread::LINK_MODES;
+use pathtemplate::{NAME_CASES, SPACE_MODES, COLLISION_POLICIES, PLACEHOLDERS, validate};
+use sources;
+
+// Lets the user edit a copy of the config, returns it if they chose to save
+pub fn run_preferences(parent: &gtk::Window, config: &Config, themes: &[String]) -> Option<Config> {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Preferences");
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.add_buttons(&[("Save", gtk::ResponseType::Ok as i32),
+                         ("Cancel", gtk::ResponseType::Cancel as i32)]);
+
+    let grid = gtk::Grid::new();
+    grid.set_column_spacing(10);
+    grid.set_row_spacing(5);
+    grid.set_border_width(10);
+    let mut row = 0;
+
+    let download_dir_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let download_dir_entry = gtk::Entry::new();
+    download_dir_entry.set_placeholder_text("Default");
+    download_dir_entry.set_text(config.download_dir.as_ref().map(|d| d.as_str()).unwrap_or(""));
+    let browse_button = gtk::Button::new_with_label("Browse");
+    download_dir_box.pack_start(&download_dir_entry, true, true, 0);
+    download_dir_box.pack_start(&browse_button, false, false, 0);
+    {
+        let dialog = dialog.clone();
+        let download_dir_entry = download_dir_entry.clone();
+        browse_button.connect_clicked(move |_| {
+            let chooser = gtk::FileChooserDialog::new(Some("Default download directory"),
+                                                      Some(&dialog),
+                                                      gtk::FileChooserAction::SelectFolder);
+            chooser.add_buttons(&[("Select", gtk::ResponseType::Ok as i32),
+                                  ("Cancel", gtk::ResponseType::Cancel as i32)]);
+            if chooser.run() == gtk::ResponseType::Ok as i32 {
+                if let Some(dir) = chooser.get_filename() {
+                    download_dir_entry.set_text(&dir.to_string_lossy());
+                }
+            }
+            chooser.destroy();
+        });
+    }
+    attach_row(&grid, &mut row, "Download directory", &download_dir_box);
+
+    let threads = spin_button(0.0, 64.0, 1.0, 0, config.threads as f64);
+    attach_row(&grid, &mut row, "Threads (0 = one per CPU)", &threads);
+    let timeout = spin_button(1.0, 120.0, 1.0, 0, config.connect_timeout_ms as f64 / 1000.0);
+    attach_row(&grid, &mut row, "Connection timeout (s)", &timeout);
+    let retries = spin_button(0.0, 20.0, 1.0, 0, config.retries as f64);
+    attach_row(&grid, &mut row, "Retries on failure", &retries);
+    let bandwidth = spin_button(0.0, 1048576.0, 16.0, 0, config.bandwidth_limit as f64 / 1024.0);
+    attach_row(&grid, &mut row, "Bandwidth limit (KiB/s, 0 = none)", &bandwidth);
+
+    let theme_combo = gtk::ComboBoxText::new();
+    let mut theme_choices = vec![AUTO_GTK_THEME.to_owned()];
+    theme_choices.extend(themes.iter().cloned());
+    for (idx, theme) in theme_choices.iter().enumerate() {
+        theme_combo.append_text(theme);
+        if config.theme.as_ref() == Some(theme) {
+            theme_combo.set_active(idx as i32);
+        }
+    }
+    attach_row(&grid, &mut row, "Theme", &theme_combo);
+
+    let formats = gtk::Entry::new();
+    formats.set_text(&config.accepted_formats.join(", "));
+    attach_row(&grid, &mut row, "Accepted formats (restart)", &formats);
+    let template = gtk::Entry::new();
+    template.set_text(&config.path_template);
+    let placeholders = PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>();
+    template.set_tooltip_text(Some(&format!("Relative to the download directory, / starts a \
+                                             folder. Placeholders: {}",
+                                            placeholders.join(" "))));
+    attach_row(&grid, &mut row, "Path template (new downloads)", &template);
+    let path_case = gtk::ComboBoxText::new();
+    for (idx, &(label, case)) in NAME_CASES.iter().enumerate() {
+        path_case.append_text(label);
+        if case == config.path_case {
+            path_case.set_active(idx as i32);
+        }
+    }
+    attach_row(&grid, &mut row, "Path case", &path_case);
+    let path_spaces = gtk::ComboBoxText::new();
+    for (idx, &(label, spaces)) in SPACE_MODES.iter().enumerate() {
+        path_spaces.append_text(label);
+        if spaces == config.path_spaces {
+            path_spaces.set_active(idx as i32);
+        }
+    }
+    attach_row(&grid, &mut row, "Spaces in paths", &path_spaces);
+    let path_max_length = spin_button(0.0, 255.0, 1.0, 0, config.path_max_length as f64);
+    attach_row(&grid, &mut row, "Max file name length (0 = none)", &path_max_length);
+    let collision_policy = gtk::ComboBoxText::new();
+    for (idx, &(label, policy)) in COLLISION_POLICIES.iter().enumerate() {
+        collision_policy.append_text(label);
+        if policy == config.collision_policy {
+            collision_policy.set_active(idx as i32);
+        }
+    }
+    attach_row(&grid, &mut row, "When two books get the same path", &collision_policy);
+    let duplicate_link = gtk::ComboBoxText::new();
+    for (idx, &(label, mode)) in LINK_MODES.iter().enumerate() {
+        duplicate_link.append_text(label);
+        if mode == config.duplicate_link {
+            duplicate_link.set_active(idx as i32);
+        }
+    }
+    attach_row(&grid, &mut row, "Books in several categories", &duplicate_link);
+    let language = gtk::Entry::new();
+    language.set_text(&config.list_language);
+    attach_row(&grid, &mut row, "List language, for {language}", &language);
+    let list_source = gtk::Entry::new();
+    list_source.set_placeholder_text("Built in");
+    list_source.set_text(config.list_source.as_ref().map(|s| s.as_str()).unwrap_or(""));
+    attach_row(&grid, &mut row, "Book list, file or url (restart)", &list_source);
+    let list_formats = sources::registered();
+    let list_format = gtk::ComboBoxText::new();
+    for (idx, source) in list_formats.iter().enumerate() {
+        list_format.append_text(source.name());
+        if source.id() == config.list_format {
+            list_format.set_active(idx as i32);
+            list_format.set_tooltip_text(Some(source.description()));
+        }
+    }
+    {
+        let descriptions = list_formats.iter()
+                                       .map(|source| source.description().to_owned())
+                                       .collect::<Vec<String>>();
+        list_format.connect_changed(move |combo| {
+            if let Some(description) = descriptions.get(combo.get_active() as usize) {
+                combo.set_tooltip_text(Some(description));
+            }
+        });
+    }
+    attach_row(&grid, &mut row, "Book list format (restart)", &list_format);
+    let awesome_heading_level = spin_button(1.0, 6.0, 1.0, 0, config.awesome_heading_level as f64);
+    attach_row(&grid,
+               &mut row,
+               "Awesome list: top category heading level",
+               &awesome_heading_level);
+    let awesome_skip_headings = gtk::Entry::new();
+    awesome_skip_headings.set_text(&config.awesome_skip_headings.join(", "));
+    attach_row(&grid,
+               &mut row,
+               "Awesome list: headings that aren't categories",
+               &awesome_skip_headings);
+    let awesome_formats_only = gtk::CheckButton::new();
+    awesome_formats_only.set_active(config.awesome_formats_only);
+    attach_row(&grid,
+               &mut row,
+               "Awesome list: only links in accepted formats",
+               &awesome_formats_only);
+    let subscriptions = gtk::Entry::new();
+    subscriptions.set_text(&config.subscriptions.join(", "));
+    subscriptions.set_tooltip_text(Some("New books in these categories are queued whenever \
+                                         the list is read"));
+    attach_row(&grid, &mut row, "Subscribed categories", &subscriptions);
+    let refresh_minutes = spin_button(0.0, 10080.0, 5.0, 0, config.refresh_minutes as f64);
+    attach_row(&grid, &mut row, "Reread the list every (min, 0 = never)", &refresh_minutes);
+    let restore_selection = gtk::CheckButton::new();
+    restore_selection.set_active(config.restore_selection);
+    attach_row(&grid, &mut row, "Reopen unfinished downloads", &restore_selection);
+
+    let gui_update = spin_button(10.0, 1000.0, 10.0, 0, config.gui_update_ms as f64);
+    attach_row(&grid, &mut row, "List update interval (ms)", &gui_update);
+    let speed_window = spin_button(0.5, 30.0, 0.5, 1, config.speed_window);
+    attach_row(&grid, &mut row, "Speed window (s)", &speed_window);
+    let speed_smoothing = spin_button(0.1, 10.0, 0.1, 1, config.speed_smoothing);
+    attach_row(&grid, &mut row, "Speed smoothing (s)", &speed_smoothing);
+
+    if let Some(content_area) = dialog.get_content_area() {
+        content_area.add(&grid);
+    }
+    dialog.show_all();
+
+    let result = if dialog.run() == gtk::ResponseType::Ok as i32 {
+        let download_dir = download_dir_entry.get_text().unwrap_or(String::new()).trim().to_owned();
+        let mut new_config = config.clone();
+        new_config.download_dir = if download_dir.is_empty() {
+            None
+        } else {
+            Some(download_dir)
+        };
+        new_config.threads = threads.get_value_as_int() as usize;
+        new_config.connect_timeout_ms = (timeout.get_value() * 1000.0) as u64;
+        new_config.retries = retries.get_value_as_int() as usize;
+        new_config.bandwidth_limit = (bandwidth.get_value() * 1024.0) as usize;
+        new_config.theme = theme_combo.get_active_text().or(config.theme.clone());
+        new_config.accepted_formats = formats.get_text()
+                                             .unwrap_or(String::new())
+                                             .split(',')
+                                             .map(|f| f.trim().trim_left_matches('.').to_lowercase())
+                                             .filter(|f| !f.is_empty())
+                                             .collect();
+        let template = template.get_text().unwrap_or(String::new());
+        match validate(&template) {
+            Ok(_) => new_config.path_template = template.trim().to_owned(),
+            Err(e) => warn!("Keeping the old path template: {}", e),
+        }
+        if let Some(&(_, case)) = NAME_CASES.get(path_case.get_active() as usize) {
+            new_config.path_case = case;
+        }
+        if let Some(&(_, spaces)) = SPACE_MODES.get(path_spaces.get_active() as usize) {
+            new_config.path_spaces = spaces;
+        }
+        new_config.path_max_length = path_max_length.get_value_as_int() as usize;
+        if let Some(&(_, mode)) = LINK_MODES.get(duplicate_link.get_active() as usize) {
+            new_config.duplicate_link = mode;
+        }
+        if let Some(&(_, policy)) = COLLISION_POLICIES.get(collision_policy.get_active() as usize) {
+            new_config.collision_policy = policy;
+        }
+        let list_source = list_source.get_text().unwrap_or(String::new()).trim().to_owned();
+        new_config.list_source = if list_source.is_empty() {
+            None
+        } else {
+            Some(list_source)
+        };
+        if let Some(source) = list_formats.get(list_format.get_active() as usize) {
+            new_config.list_format = source.id().to_owned();
+        }
+        new_config.awesome_heading_level = awesome_heading_level.get_value_as_int() as usize;
+        new_config.awesome_skip_headings = awesome_skip_headings.get_text()
+                                                                .unwrap_or(String::new())
+                                                                .split(',')
+                                                                .map(|s| s.trim().to_owned())
+                                                                .filter(|s| !s.is_empty())
+                                                                .collect();
+        new_config.awesome_formats_only = awesome_formats_only.get_active();
+        new_config.subscriptions = subscriptions.get_text()
+                                                .unwrap_or(String::new())
+                                                .split(',')
+                                                .map(|s| s.trim().to_owned())
+                                                .filter(|s| !s.is_empty())
+                                                .collect();
+        new_config.refresh_minutes = refresh_minutes.get_value_as_int() as u64;
+        new_config.restore_selection = restore_selection.get_active();
+        let language = language.get_text().unwrap_or(String::new());
+        if !language.trim().is_empty() {
+            new_config.list_language = language.trim().to_owned();
+        }
+        new_config.gui_update_ms = gui_update.get_value_as_int() as u64;
+        new_config.speed_window = speed_window.get_value();
+        new_config.speed_smoothing = speed_smoothing.get_value();
+        Some(new_config)
+    } else {
+        None
+    };
+    dialog.destroy();
+    result
+}
+
+fn spin_button(min: f64, max: f64, step: f64, digits: u32, value: f64) -> gtk::SpinButton {
+    let spin_button = gtk::SpinButton::new_with_range(min, max, step);
+    spin_button.set_digits(digits);
+    spin_button.set_value(value);
+    spin_button
+}
+
+fn attach_row<T: IsA<gtk::Widget>>(grid: &gtk::Grid, row: &mut i32, label: &str, widget: &T) {
+    let label = gtk::Label::new(Some(label));
+    label.set_halign(gtk::Align::End);
+    grid.attach(&label, 0, *row, 1, 1);
+    grid.attach(widget, 1, *row, 1, 1);
+    *row += 1;
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use helper::maximum;
+use constants::{DOWNLOAD_SPEED_HISTORY_INTERVAL, DOWNLOAD_SPEED_HISTORY_LEN};
+use config;
+
+// Throughput over a sliding window of timestamped samples, smoothed with an
+// exponentially weighted moving average so the display doesn't jitter.
+// Feed it every update (zero bytes included) so a stalled download decays to 0.
+#[derive(Debug, Clone)]
+pub struct RateEstimator {
+    // bytes received within this long ago make up the current rate
+    window: f64,
+    // time constant of the moving average laid over the windowed rate
+    smooth_time: f64,
+    // (time in s, bytes) for every sample still inside the window
+    samples: VecDeque<(f64, usize)>,
+    window_bytes: usize,
+    start_time: f64,
+    last_time: f64,
+    // bytes per second
+    smoothed: f64,
+    // smoothed speed taken every DOWNLOAD_SPEED_HISTORY_INTERVAL, oldest first
+    history: VecDeque<f32>,
+    next_history_time: f64,
+}
+
+impl RateEstimator {
+    // window and smooth_time in seconds
+    pub fn new(now: f64, window: f64, smooth_time: f64) -> RateEstimator {
+        RateEstimator {
+            window: maximum(window, 0.1),
+            smooth_time: maximum(smooth_time, 0.01),
+            samples: VecDeque::new(),
+            window_bytes: 0,
+            start_time: now,
+            last_time: now,
+            smoothed: 0.0,
+            history: VecDeque::with_capacity(DOWNLOAD_SPEED_HISTORY_LEN),
+            next_history_time: now + DOWNLOAD_SPEED_HISTORY_INTERVAL,
+        }
+    }
+
+    pub fn from_config(now: f64) -> RateEstimator {
+        let (window, smooth_time) = config::with(|c| (c.speed_window, c.speed_smoothing));
+        RateEstimator::new(now, window, smooth_time)
+    }
+
+    pub fn add_sample(&mut self, now: f64, bytes: usize) {
+        self.samples.push_back((now, bytes));
+        self.window_bytes += bytes;
+        while let Some(&(time, old_bytes)) = self.samples.front() {
+            if time >= now - self.window {
+                break;
+            }
+            self.window_bytes -= old_bytes;
+            self.samples.pop_front();
+        }
+
+        // the window can't reach back past the start of the download
+        let span = maximum(self.window.min(now - self.start_time), 0.001);
+        let window_rate = self.window_bytes as f64 / span;
+        // time-aware ewma so irregular update intervals weigh correctly
+        let dt = maximum(now - self.last_time, 0.0);
+        let alpha = 1.0 - (-dt / self.smooth_time).exp();
+        self.smoothed += alpha * (window_rate - self.smoothed);
+        self.last_time = now;
+
+        while now >= self.next_history_time {
+            if self.history.len() >= DOWNLOAD_SPEED_HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back(self.smoothed as f32);
+            self.next_history_time += DOWNLOAD_SPEED_HISTORY_INTERVAL;
+        }
+    }
+
+    // bytes per second
+    pub fn rate(&self) -> f64 {
+        self.smoothed
+    }
+
+    pub fn history(&self) -> &VecDeque<f32> {
+        &self.history
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.window_bytes = 0;
+        self.smoothed = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateEstimator;
+
+    // a sample every 0.1s from 0.1s on, the number of bytes picked by its time
+    fn feed<F>(estimator: &mut RateEstimator, from: usize, to: usize, bytes: F)
+        where F: Fn(f64) -> usize
+    {
+        for step in from..to {
+            let now = step as f64 * 0.1;
+            estimator.add_sample(now, bytes(now));
+        }
+    }
+
+    #[test]
+    fn follows_a_steady_rate() {
+        let mut estimator = RateEstimator::new(0.0, 1.0, 0.5);
+        feed(&mut estimator, 1, 51, |_| 1000);
+        assert!(estimator.rate() > 9000.0 && estimator.rate() < 12000.0);
+    }
+
+    #[test]
+    fn decays_to_zero_without_data() {
+        let mut estimator = RateEstimator::new(0.0, 1.0, 0.5);
+        feed(&mut estimator, 1, 21, |_| 1000);
+        feed(&mut estimator, 21, 121, |_| 0);
+        assert!(estimator.rate() < 0.01);
+    }
+
+    #[test]
+    fn reset_forgets_the_rate() {
+        let mut estimator = RateEstimator::new(0.0, 1.0, 0.5);
+        feed(&mut estimator, 1, 21, |_| 1000);
+        estimator.reset();
+        assert_eq!(estimator.rate(), 0.0);
+    }
+}
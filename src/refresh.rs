@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use args::{Args, category_matches};
+use data::{Category, Download, GuiCmdMsg};
+use parse::read_list;
+use sources;
+use config;
+
+// --list for this session, else the one in the config, None for the built in list
+pub fn list_source(args: &Args) -> Option<String> {
+    args.list.clone().or(config::startup().list_source.clone())
+}
+
+// the configured subscriptions plus the ones from --subscribe
+pub fn subscriptions(extra: &[String]) -> Vec<String> {
+    let mut subscriptions = config::with(|c| c.subscriptions.clone());
+    subscriptions.extend(extra.iter().cloned());
+    subscriptions
+}
+
+pub fn subscribed(category_name: &str, subscriptions: &[String]) -> bool {
+    subscriptions.iter().any(|wanted| category_matches(category_name, wanted))
+}
+
+// Rereads the book list and queues what's new in the subscribed categories. Books that are
+// new anywhere are handed to the comm handler, which passes them on to the sidebar.
+pub struct Refresher {
+    source: String,
+    // id of the format it's written in
+    format: String,
+    // every book seen so far, in any version of the list
+    known: HashSet<u64>,
+    // from --subscribe, the configured ones are read on each refresh
+    extra_subscriptions: Vec<String>,
+    // --refresh, overrides the configured interval
+    refresh_minutes: Option<u64>,
+}
+
+impl Refresher {
+    pub fn new(source: String, data: &[Category], args: &Args) -> Refresher {
+        Refresher {
+            source: source,
+            format: sources::format_id(args),
+            known: data.iter()
+                       .flat_map(|category| category.downloads().iter().map(|d| d.id()))
+                       .collect(),
+            extra_subscriptions: args.subscribe.clone(),
+            refresh_minutes: args.refresh,
+        }
+    }
+
+    // minutes between refreshes, 0 if it's off
+    pub fn refresh_minutes(&self) -> u64 {
+        self.refresh_minutes.unwrap_or(config::with(|c| c.refresh_minutes))
+    }
+
+    // returns how many books were queued
+    pub fn refresh(&mut self,
+                   command_send: &Sender<GuiCmdMsg>,
+                   download_dir: &Path)
+                   -> Result<usize, String> {
+        let (data, _) = sources::by_id(&self.format).parse(&try!(read_list(&self.source)));
+        let subscriptions = subscriptions(&self.extra_subscriptions);
+        let mut new_downloads: Vec<Download> = Vec::new();
+        let mut queued_ids = Vec::new();
+        for category in data.iter() {
+            for download in category.downloads() {
+                if self.known.insert(download.id()) {
+                    new_downloads.push(download.clone());
+                    if subscribed(category.name(), &subscriptions) {
+                        queued_ids.push(download.id());
+                    }
+                }
+            }
+        }
+        info!("{} new books in {}, queueing {}",
+              new_downloads.len(),
+              self.source,
+              queued_ids.len());
+        if !new_downloads.is_empty() {
+            try!(command_send.send(GuiCmdMsg::Extend(new_downloads))
+                             .map_err(|e| format!("Failed to pass on the new books: {}", e)));
+        }
+        for &id in queued_ids.iter() {
+            try!(command_send.send(GuiCmdMsg::Add(id, download_dir.to_path_buf()))
+                             .map_err(|e| format!("Failed to queue a new book: {}", e)));
+        }
+        Ok(queued_ids.len())
+    }
+}
+
+// Refreshes in the background on the interval, which is reread every minute so a change in
+// the preferences takes effect without a restart
+pub fn spawn_refresher(refresher: Arc<Mutex<Refresher>>,
+                       command_send: Sender<GuiCmdMsg>,
+                       download_dir: Arc<Mutex<PathBuf>>) {
+    thread::spawn(move || {
+        let mut waited = 0;
+        loop {
+            thread::sleep(Duration::from_secs(60));
+            waited += 1;
+            let refresh_minutes = refresher.lock().unwrap().refresh_minutes();
+            if refresh_minutes == 0 || waited < refresh_minutes {
+                continue;
+            }
+            waited = 0;
+            let download_dir = download_dir.lock().unwrap().to_path_buf();
+            if let Err(e) = refresher.lock().unwrap().refresh(&command_send, &download_dir) {
+                warn!("{}", e);
+            }
+        }
+    });
+}
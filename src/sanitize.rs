@@ -0,0 +1,156 @@
+use std::ascii::AsciiExt;
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+// not allowed in a file name on at least one of windows, macos or linux
+const RESERVED_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+// device names on windows, with or without an extension
+const RESERVED_NAMES: [&'static str; 22] = ["CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3",
+                                            "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+                                            "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+                                            "LPT7", "LPT8", "LPT9"];
+
+// most filesystems allow 255 bytes, this leaves room for ".tmp" and a " (2)" suffix
+const MAX_NAME_BYTES: usize = 240;
+
+// used when nothing of a name is left
+const EMPTY_NAME: &'static str = "untitled";
+
+// Makes a single file or directory name safe on any common filesystem.
+// "CON.pdf" -> "_CON.pdf", "Foo: Bar?.pdf" -> "Foo_ Bar_.pdf"
+pub fn sanitize_component(name: &str) -> String {
+    let normalized = name.nfc().collect::<String>();
+    let replaced = normalized.chars()
+                             .map(|c| {
+                                 if c.is_control() || RESERVED_CHARS.contains(&c) {
+                                     '_'
+                                 } else {
+                                     c
+                                 }
+                             })
+                             .collect::<String>();
+    // leading dots hide files or climb directories, windows drops trailing ones
+    let trimmed = replaced.trim_left_matches(|c: char| c == '.' || c.is_whitespace())
+                          .trim_right_matches(|c: char| c == '.' || c.is_whitespace());
+    let mut sanitized = truncate_name(trimmed, MAX_NAME_BYTES);
+    if sanitized.is_empty() {
+        sanitized = EMPTY_NAME.to_owned();
+    }
+    let stem = sanitized.split('.').next().unwrap_or("").trim_right().to_ascii_uppercase();
+    if RESERVED_NAMES.iter().any(|&reserved| reserved == stem) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+// Joins a relative path onto the download root, refusing anything that could end up outside it
+pub fn contained_path(root: &Path, relative: &Path) -> Result<PathBuf, String> {
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => {}
+            _ => {
+                return Err(format!("{:?} would leave the download directory {:?}",
+                                   relative,
+                                   root))
+            }
+        }
+    }
+    if path == root {
+        return Err(format!("{:?} doesn't name a file", relative));
+    }
+    Ok(path)
+}
+
+// cuts a name to a number of bytes on a char boundary, keeping a short extension
+fn truncate_name(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_owned();
+    }
+    let ext = match name.rfind('.') {
+        Some(idx) if name.len() - idx <= 8 => &name[idx..],
+        _ => "",
+    };
+    let mut end = max_bytes - ext.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", name[..end].trim_right(), ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::{sanitize_component, contained_path, MAX_NAME_BYTES, EMPTY_NAME};
+
+    #[test]
+    fn replaces_reserved_chars() {
+        assert_eq!(sanitize_component("Foo: Bar?.pdf"), "Foo_ Bar_.pdf");
+        assert_eq!(sanitize_component("a/b\\c.pdf"), "a_b_c.pdf");
+    }
+
+    #[test]
+    fn empty_names_get_a_placeholder() {
+        assert_eq!(sanitize_component("...."), EMPTY_NAME);
+        assert_eq!(sanitize_component(" \t  "), EMPTY_NAME);
+        assert_eq!(sanitize_component(""), EMPTY_NAME);
+    }
+
+    #[test]
+    fn trims_dots_and_whitespace() {
+        assert_eq!(sanitize_component("..hidden.pdf"), "hidden.pdf");
+        assert_eq!(sanitize_component("  name. . "), "name");
+        assert_eq!(sanitize_component(". .hidden"), "hidden");
+    }
+
+    #[test]
+    fn escapes_windows_device_names() {
+        assert_eq!(sanitize_component("CON.pdf"), "_CON.pdf");
+        assert_eq!(sanitize_component("lpt1"), "_lpt1");
+        assert_eq!(sanitize_component("CONSOLE.pdf"), "CONSOLE.pdf");
+    }
+
+    #[test]
+    fn normalizes_to_nfc() {
+        // e followed by a combining acute accent
+        assert_eq!(sanitize_component("Caf\u{65}\u{301}.pdf"), "Caf\u{e9}.pdf");
+    }
+
+    #[test]
+    fn truncates_on_a_char_boundary() {
+        // three bytes each, the cut for the extension lands inside one
+        let long = format!("{}.pdf", "\u{20ac}".repeat(100));
+        let truncated = sanitize_component(&long);
+        assert!(truncated.len() <= MAX_NAME_BYTES);
+        assert_eq!(truncated, format!("{}.pdf", "\u{20ac}".repeat(78)));
+    }
+
+    #[test]
+    fn keeps_paths_inside_the_root() {
+        let root = Path::new("downloads");
+        assert_eq!(contained_path(root, Path::new("a/./b.pdf")),
+                   Ok(root.join("a").join("b.pdf")));
+        assert!(contained_path(root, Path::new("../b.pdf")).is_err());
+        assert!(contained_path(root, Path::new("a/../../b.pdf")).is_err());
+        assert!(contained_path(root, Path::new("/etc/passwd")).is_err());
+        assert!(contained_path(root, Path::new(".")).is_err());
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn refuses_drive_paths() {
+        let root = Path::new("downloads");
+        assert!(contained_path(root, Path::new("C:\\b.pdf")).is_err());
+        assert!(contained_path(root, Path::new("C:b.pdf")).is_err());
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn drive_paths_are_plain_names_elsewhere() {
+        let root = Path::new("downloads");
+        assert_eq!(contained_path(root, Path::new("C:/b.pdf")),
+                   Ok(root.join("C:").join("b.pdf")));
+    }
+}
@@ -0,0 +1,44 @@
+use gtk;
+use gtk::prelude::*;
+use glib::types::Type;
+use diagnostics::{Diagnostics, summarize};
+use cellrenderers::*;
+
+// Lists the lines of the book list the parser left out and why
+pub fn run_skipped(parent: &gtk::Window, diagnostics: &Diagnostics) {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Skipped entries");
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.set_default_size(900, 500);
+    dialog.add_buttons(&[("Close", gtk::ResponseType::Close as i32)]);
+
+    // line, reason, category, text
+    let store = gtk::ListStore::new(&[Type::String, Type::String, Type::String, Type::String]);
+    for entry in diagnostics.entries() {
+        let iter = store.append();
+        store.set_value(&iter, 0, &entry.line.to_string().to_value());
+        store.set_value(&iter, 1, &entry.kind.label().to_value());
+        store.set_value(&iter, 2, &entry.category.to_value());
+        store.set_value(&iter, 3, &entry.text.to_value());
+    }
+    let view = gtk::TreeView::new();
+    view.set_model(Some(&store));
+    view.add_text_renderer_column("Line", true, true, false, AddMode::PackStart, false, 0);
+    view.add_text_renderer_column("Reason", true, true, false, AddMode::PackStart, false, 1);
+    view.add_text_renderer_column("Category", true, true, false, AddMode::PackStart, true, 2);
+    view.add_text_renderer_column("Text", true, true, true, AddMode::PackStart, true, 3);
+    let scroll = gtk::ScrolledWindow::new(None, None);
+    scroll.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+    scroll.add(&view);
+
+    let status = gtk::Label::new(Some(&summarize(diagnostics)));
+    status.set_halign(gtk::Align::Start);
+    if let Some(content_area) = dialog.get_content_area() {
+        content_area.pack_start(&status, false, false, 5);
+        content_area.pack_start(&scroll, true, true, 0);
+    }
+    dialog.show_all();
+    dialog.run();
+    dialog.destroy();
+}
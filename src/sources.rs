@@ -0,0 +1,338 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use rustc_serialize::json::{Json, ParserError};
+use args::Args;
+use data::{Category, Download, category_path};
+use diagnostics::{Diagnostics, DiagnosticKind};
+use parse::{ParseRules, parse_markdown};
+use constants::CATEGORY_SEPARATOR;
+use config;
+
+// the format of the built in list
+pub const DEFAULT_FORMAT: &'static str = "fpb";
+
+// category of the books a table or json list doesn't give one
+const UNCATEGORIZED: &'static str = "Uncategorized";
+
+// A kind of book list that can be read into categories
+pub trait ListSource {
+    // kept in the config and given to --format
+    fn id(&self) -> &'static str;
+    fn name(&self) -> &'static str;
+    // what the list has to look like
+    fn description(&self) -> &'static str;
+    fn parse(&self, contents: &str) -> (Vec<Category>, Diagnostics);
+}
+
+// every format there is, in the order the preferences show them
+pub fn registered() -> Vec<Box<ListSource>> {
+    let mut sources: Vec<Box<ListSource>> = Vec::new();
+    sources.push(Box::new(FreeProgrammingBooks));
+    sources.push(Box::new(AwesomeList));
+    sources.push(Box::new(Table {
+        id: "csv",
+        name: "CSV",
+        description: "One book per line: title, url, category, author",
+        delimiter: ',',
+    }));
+    sources.push(Box::new(Table {
+        id: "tsv",
+        name: "TSV",
+        description: "One book per line, tab separated: title, url, category, author",
+        delimiter: '\t',
+    }));
+    sources.push(Box::new(JsonCatalog));
+    sources
+}
+
+pub fn find(id: &str) -> Option<Box<ListSource>> {
+    registered().into_iter().find(|source| source.id().eq_ignore_ascii_case(id.trim()))
+}
+
+// an unknown format is read like the built in list
+pub fn by_id(id: &str) -> Box<ListSource> {
+    find(id).unwrap_or_else(|| {
+        warn!("Unknown list format \"{}\", reading it as {}", id, DEFAULT_FORMAT);
+        Box::new(FreeProgrammingBooks) as Box<ListSource>
+    })
+}
+
+// --format for this session, else the configured one
+pub fn format_id(args: &Args) -> String {
+    args.format.clone().unwrap_or(config::startup().list_format.clone())
+}
+
+pub struct FreeProgrammingBooks;
+
+impl ListSource for FreeProgrammingBooks {
+    fn id(&self) -> &'static str {
+        DEFAULT_FORMAT
+    }
+
+    fn name(&self) -> &'static str {
+        "free-programming-books"
+    }
+
+    fn description(&self) -> &'static str {
+        "Markdown with a \"## Category\" heading above \"* [Title](url) - Author\" items"
+    }
+
+    fn parse(&self, contents: &str) -> (Vec<Category>, Diagnostics) {
+        parse_markdown(contents, &ParseRules::free_programming_books())
+    }
+}
+
+// Markdown like the free-programming-books list, with the headings and links that count
+// taken from the config
+pub struct AwesomeList;
+
+impl ListSource for AwesomeList {
+    fn id(&self) -> &'static str {
+        "awesome"
+    }
+
+    fn name(&self) -> &'static str {
+        "Awesome list"
+    }
+
+    fn description(&self) -> &'static str {
+        "Markdown with \"- [Name](url) - Description\" items under headings"
+    }
+
+    fn parse(&self, contents: &str) -> (Vec<Category>, Diagnostics) {
+        let rules = config::with(|c| {
+            ParseRules {
+                title_identifier: '#',
+                top_level: c.awesome_heading_level,
+                skip_headings: c.awesome_skip_headings.clone(),
+                formats_only: c.awesome_formats_only,
+                authors: false,
+            }
+        });
+        parse_markdown(contents, &rules)
+    }
+}
+
+// CSV or TSV, the first line can name the columns
+pub struct Table {
+    id: &'static str,
+    name: &'static str,
+    description: &'static str,
+    delimiter: char,
+}
+
+impl ListSource for Table {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn parse(&self, contents: &str) -> (Vec<Category>, Diagnostics) {
+        let mut diagnostics = Diagnostics::new();
+        let mut books = Books::new();
+        let mut first_row = true;
+        for (idx, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_row(line, self.delimiter);
+            let field = |n: usize| fields.get(n).map(|field| field.trim()).unwrap_or("");
+            if first_row {
+                first_row = false;
+                if field(0).eq_ignore_ascii_case("title") {
+                    continue;
+                }
+            }
+            if field(0).is_empty() || field(1).is_empty() {
+                diagnostics.push(idx + 1, DiagnosticKind::MalformedLink, field(2), line);
+                continue;
+            }
+            let author = if field(3).is_empty() {
+                None
+            } else {
+                Some(field(3).to_owned())
+            };
+            books.add(idx + 1, line, field(0), field(1), field(2), author, &mut diagnostics);
+        }
+        (books.into_categories(), diagnostics)
+    }
+}
+
+// [{"title": .., "url": .., "category": "A / B" or ["A", "B"], "author": ..}], or the same
+// array under "books"
+pub struct JsonCatalog;
+
+impl ListSource for JsonCatalog {
+    fn id(&self) -> &'static str {
+        "json"
+    }
+
+    fn name(&self) -> &'static str {
+        "JSON"
+    }
+
+    fn description(&self) -> &'static str {
+        "An array of {\"title\", \"url\", \"category\", \"author\"} objects"
+    }
+
+    fn parse(&self, contents: &str) -> (Vec<Category>, Diagnostics) {
+        let mut diagnostics = Diagnostics::new();
+        let entries = match Json::from_str(contents) {
+            Ok(Json::Array(entries)) => entries,
+            Ok(Json::Object(mut object)) => {
+                match object.remove("books") {
+                    Some(Json::Array(entries)) => entries,
+                    _ => {
+                        diagnostics.push(1,
+                                         DiagnosticKind::InvalidList,
+                                         "",
+                                         "Expected an array of books or a \"books\" array");
+                        return (Vec::new(), diagnostics);
+                    }
+                }
+            }
+            Ok(_) => {
+                diagnostics.push(1, DiagnosticKind::InvalidList, "", "Expected an array of books");
+                return (Vec::new(), diagnostics);
+            }
+            Err(e) => {
+                let line = match e {
+                    ParserError::SyntaxError(_, line, _) => line,
+                    _ => 1,
+                };
+                diagnostics.push(line, DiagnosticKind::InvalidList, "", &format!("{}", e));
+                return (Vec::new(), diagnostics);
+            }
+        };
+        let mut books = Books::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            let text = entry.to_string();
+            let field = |key: &str| {
+                entry.find(key)
+                     .and_then(|value| value.as_string())
+                     .map(|value| value.trim().to_owned())
+                     .unwrap_or(String::new())
+            };
+            let category = match entry.find("category") {
+                Some(&Json::Array(ref parts)) => {
+                    parts.iter()
+                         .filter_map(|part| part.as_string())
+                         .collect::<Vec<&str>>()
+                         .join(CATEGORY_SEPARATOR)
+                }
+                _ => field("category"),
+            };
+            let (title, url) = (field("title"), field("url"));
+            if title.is_empty() || url.is_empty() {
+                diagnostics.push(idx + 1, DiagnosticKind::MalformedLink, &category, &text);
+                continue;
+            }
+            let author = entry.find("author").and_then(|author| author.as_string()).map(|author| {
+                author.trim().to_owned()
+            });
+            books.add(idx + 1, &text, &title, &url, &category, author, &mut diagnostics);
+        }
+        (books.into_categories(), diagnostics)
+    }
+}
+
+// Groups books given one at a time into categories, "A / B" nests B in A
+struct Books {
+    categories: Vec<Category>,
+    // (category, title) -> times seen, later ones get a number like in the markdown lists
+    titles: HashMap<(String, String), usize>,
+}
+
+impl Books {
+    fn new() -> Books {
+        Books {
+            categories: Vec::new(),
+            titles: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self,
+           line: usize,
+           text: &str,
+           title: &str,
+           url: &str,
+           category: &str,
+           author: Option<String>,
+           diagnostics: &mut Diagnostics) {
+        let mut path = category_path(category)
+                           .into_iter()
+                           .map(|title| title.trim().to_owned())
+                           .filter(|title| !title.is_empty())
+                           .collect::<Vec<String>>();
+        if path.is_empty() {
+            path.push(UNCATEGORIZED.to_owned());
+        }
+        // parents first, so every subcategory has one
+        for depth in 1..path.len() + 1 {
+            if !self.categories.iter().any(|c| c.path() == &path[..depth]) {
+                self.categories.push(Category::new(path[..depth].to_vec(), Vec::new()));
+            }
+        }
+        let category = self.categories
+                           .iter_mut()
+                           .find(|c| c.path() == &path[..])
+                           .expect("Category was just added");
+        let seen = self.titles
+                       .entry((category.name().to_owned(), title.to_owned()))
+                       .or_insert(0);
+        let name = if *seen > 0 {
+            diagnostics.push(line, DiagnosticKind::DuplicateTitle, category.name(), text);
+            format!("{} {}", title, seen)
+        } else {
+            title.to_owned()
+        };
+        *seen += 1;
+        let mut download = Download::new(&name, url, Some(category.name().to_owned()));
+        download.set_author(author);
+        category.add_download(download);
+    }
+
+    // subcategories have to follow their parent, see data::subtree
+    fn into_categories(self) -> Vec<Category> {
+        let mut categories = self.categories;
+        categories.sort_by(|a, b| a.path().cmp(b.path()));
+        categories
+    }
+}
+
+// "a,\"b, c\",d" -> ["a", "b, c", "d"], "" in quotes is a quote. Fields can't span lines.
+fn split_row(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c != '"' {
+                field.push(c);
+            } else if chars.peek() == Some(&'"') {
+                field.push('"');
+                chars.next();
+            } else {
+                quoted = false;
+            }
+        } else if c == '"' && field.trim().is_empty() {
+            field.clear();
+            quoted = true;
+        } else if c == delimiter {
+            fields.push(field);
+            field = String::new();
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+    fields
+}
@@ -1,86 +1,367 @@
+use std::env;
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs::{File, read_dir, metadata};
 use std::path::{Path, PathBuf};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::thread::LocalKey;
+use std::time::SystemTime;
 
 use gdk::Screen;
-use gtk::{CssProvider, STYLE_PROVIDER_PRIORITY_APPLICATION, StyleContext};
+use glib;
+use glib::Continue;
+use gtk::{CssProvider, STYLE_PROVIDER_PRIORITY_APPLICATION, STYLE_PROVIDER_PRIORITY_USER,
+          StyleContext};
 
 use gtkdef::*;
-use constants::GTK_THEME_CFG;
+use helper::maximum;
+use paths;
+use config;
+use constants::{GTK_THEME_CFG, DEFAULT_GTK_THEME, AUTO_GTK_THEME, THEMES_DIR_NAME, USER_GTK_CSS,
+                THEME_WATCH_INTERVAL};
+
+// (family, light variant, dark variant) of the themes we ship, arc-darker is a light theme
+// with a dark title bar
+const THEME_VARIANTS: [(&'static str, &'static str, &'static str); 5] =
+    [("arc", "arc", "arc-dark"),
+     ("arc-darker", "arc-darker", "arc-dark"),
+     ("arc-solid", "arc-solid", "arc-dark-solid"),
+     ("arc-darker-solid", "arc-darker-solid", "arc-dark-solid"),
+     ("iris", "iris-light", "iris-dark")];
+
+// provider of the theme currently applied, replaced when switching themes
+thread_local!{
+    static ACTIVE_PROVIDER: RefCell<Option<CssProvider>> = RefCell::new(None);
+    static ACTIVE_THEME: RefCell<Option<String>> = RefCell::new(None);
+    // whether the active theme was picked by the desktop's preference
+    static AUTO_MODE: Cell<bool> = Cell::new(false);
+    // the user's own gtk.css, layered over the theme
+    static USER_PROVIDER: RefCell<Option<CssProvider>> = RefCell::new(None)
+}
+
 // Manually implemented with a trait until its implemented in the main branch
 pub fn setup_theme(current_working_dir: &Path,
                    default_config_path: PathBuf,
-                   secondary_config_path: PathBuf) {
-    match get_theme_dir(&current_working_dir) {
-        Ok(theme_dir) => {
-            // check if gtk css config exists, if so use it
-            let mut gtk_theme = String::new();
-            match File::open(current_working_dir.join("..").join(GTK_THEME_CFG)) {
-                Ok(ref mut gtk_cfg_file) => {
-                    match gtk_cfg_file.read_to_string(&mut gtk_theme) {
-                        Ok(_) => {}
-                        Err(_) => {
-                            println!("Could not read theme.txt file to string, defaulting to Arc \
-                                      theme.");
-                            gtk_theme = "arc".to_owned();
-                        }
-                    }
-                }
-                Err(_) => {
-                    match File::open(current_working_dir.join(GTK_THEME_CFG)) {
-                        Ok(ref mut gtk_cfg_file) => {
-                            match gtk_cfg_file.read_to_string(&mut gtk_theme) {
-                                Ok(_) => {}
-                                Err(_) => {
-                                    println!("Could not read theme.txt file to string, \
-                                              defaulting to Arc theme.");
-                                    gtk_theme = "arc".to_owned();
-                                }
-                            }
-                        }
-                        Err(_) => {
-                            println!("No theme.txt file found, defaulting to Arc theme.");
-                            gtk_theme = "arc".to_owned();
-                        }
-                    }
+                   secondary_config_path: PathBuf,
+                   theme_override: Option<&str>) {
+    let gtk_theme = match theme_override {
+        Some(theme) => theme.to_owned(),
+        None => read_theme_choice(current_working_dir),
+    };
+    info!("Using theme {}", gtk_theme);
+    if let Err(e) = choose_theme(current_working_dir,
+                                 &default_config_path,
+                                 &secondary_config_path,
+                                 &gtk_theme) {
+        warn!("{}", e);
+        if gtk_theme != DEFAULT_GTK_THEME {
+            info!("Falling back to the {} theme.", DEFAULT_GTK_THEME);
+            if let Err(e) = apply_theme(current_working_dir,
+                                        &default_config_path,
+                                        &secondary_config_path,
+                                        DEFAULT_GTK_THEME) {
+                warn!("{}", e);
+                no_css_error();
+            }
+        } else {
+            no_css_error();
+        }
+    }
+    if let Err(e) = load_user_css() {
+        warn!("{}", e);
+    }
+}
+
+// Applies a theme chosen by the user, "auto" picks the variant matching the desktop.
+pub fn choose_theme(current_working_dir: &Path,
+                    default_config_path: &Path,
+                    secondary_config_path: &Path,
+                    choice: &str)
+                    -> Result<(), String> {
+    let auto = choice == AUTO_GTK_THEME;
+    let gtk_theme = if auto {
+        resolve_auto_theme(current_working_dir)
+    } else {
+        choice.to_owned()
+    };
+    try!(apply_theme(current_working_dir,
+                     default_config_path,
+                     secondary_config_path,
+                     &gtk_theme));
+    AUTO_MODE.with(|auto_mode| auto_mode.set(auto));
+    Ok(())
+}
+
+// Replaces the active theme with the one in the given theme folder.
+// The current theme is kept if the new one fails to load.
+pub fn apply_theme(current_working_dir: &Path,
+                   default_config_path: &Path,
+                   secondary_config_path: &Path,
+                   gtk_theme: &str)
+                   -> Result<(), String> {
+    let gtk_theme_path = try!(find_theme(current_working_dir, gtk_theme)).join("gtk.css");
+    let config_path = try!(get_config_path(default_config_path, secondary_config_path));
+    let new_css = get_gtk_css(&config_path, &gtk_theme_path);
+    let style_provider = try!(CssProvider::load_from_data(&new_css)
+                                  .map_err(|e| format!("Failed to load theme {}: {:?}", gtk_theme, e)));
+    try!(replace_provider(&ACTIVE_PROVIDER,
+                          Some(style_provider),
+                          STYLE_PROVIDER_PRIORITY_APPLICATION as u32));
+    ACTIVE_THEME.with(|active| *active.borrow_mut() = Some(gtk_theme.to_owned()));
+    Ok(())
+}
+
+// (Re)loads the user's gtk.css from the config dir, dropping it if it was removed.
+// A stylesheet that fails to parse leaves the previous one in place.
+pub fn load_user_css() -> Result<(), String> {
+    let provider = match user_css_path() {
+        Some(ref path) if path.exists() => {
+            let mut css = String::new();
+            try!(File::open(path)
+                     .and_then(|mut f| f.read_to_string(&mut css))
+                     .map_err(|e| format!("Could not read user css {:?}: {}", path, e)));
+            Some(try!(CssProvider::load_from_data(&css)
+                          .map_err(|e| format!("Failed to load user css {:?}: {:?}", path, e))))
+        }
+        _ => None,
+    };
+    replace_provider(&USER_PROVIDER, provider, STYLE_PROVIDER_PRIORITY_USER as u32)
+}
+
+// Polls every stylesheet in use and reloads when one of them changes,
+// so styles can be tweaked without restarting.
+pub fn watch_theme_files(current_working_dir: PathBuf,
+                         default_config_path: PathBuf,
+                         secondary_config_path: PathBuf) {
+    let theme_files = {
+        let current_working_dir = current_working_dir.clone();
+        let default_config_path = default_config_path.clone();
+        let secondary_config_path = secondary_config_path.clone();
+        move || {
+            let mut files = Vec::new();
+            if let Ok(config_path) = get_config_path(&default_config_path, &secondary_config_path) {
+                files.push(config_path);
+            }
+            let active_theme = ACTIVE_THEME.with(|active| active.borrow().clone());
+            if let Some(theme_dir) = active_theme.and_then(|t| find_theme(&current_working_dir, &t).ok()) {
+                collect_css_files(&theme_dir, &mut files);
+            }
+            files
+        }
+    };
+    let mut theme_stamps = modified_times(&theme_files());
+    let mut user_stamps = modified_times(&user_css_path().into_iter().collect::<Vec<_>>());
+    glib::timeout_add(THEME_WATCH_INTERVAL, move || {
+        let new_theme_stamps = modified_times(&theme_files());
+        if new_theme_stamps != theme_stamps {
+            theme_stamps = new_theme_stamps;
+            if let Some(gtk_theme) = ACTIVE_THEME.with(|active| active.borrow().clone()) {
+                info!("Theme files changed, reloading {}", gtk_theme);
+                if let Err(e) = apply_theme(&current_working_dir,
+                                            &default_config_path,
+                                            &secondary_config_path,
+                                            &gtk_theme) {
+                    warn!("{}", e);
                 }
             }
-            gtk_theme = gtk_theme.trim().to_owned();
-            let gtk_theme_path = theme_dir.join(gtk_theme).join("gtk.css");
-            if default_config_path.exists() {
-                let new_css = get_gtk_css(&default_config_path, &gtk_theme_path);
-                if let Ok(style_provider) = CssProvider::load_from_data(&new_css) {
-                    if let Some(screen) = Screen::get_default() {
-                        StyleContext::add_provider_for_screen(&screen,
-                                                              &style_provider,
-                                                              STYLE_PROVIDER_PRIORITY_APPLICATION as u32);
-                    } else {
-                        no_css_error();
+        }
+        let new_user_stamps = modified_times(&user_css_path().into_iter().collect::<Vec<_>>());
+        if new_user_stamps != user_stamps {
+            user_stamps = new_user_stamps;
+            info!("User css changed, reloading");
+            if let Err(e) = load_user_css() {
+                warn!("{}", e);
+            }
+        }
+        Continue(true)
+    });
+}
+
+// Follows the desktop switching between light and dark while the theme is "auto".
+pub fn watch_desktop_theme(current_working_dir: PathBuf,
+                           default_config_path: PathBuf,
+                           secondary_config_path: PathBuf) {
+    let follow = Rc::new(move || {
+        if !AUTO_MODE.with(|auto_mode| auto_mode.get()) {
+            return;
+        }
+        let wanted = resolve_auto_theme(&current_working_dir);
+        let active = ACTIVE_THEME.with(|active| active.borrow().clone());
+        if active.as_ref() != Some(&wanted) {
+            info!("Desktop preference changed, switching to {}", wanted);
+            if let Err(e) = apply_theme(&current_working_dir,
+                                        &default_config_path,
+                                        &secondary_config_path,
+                                        &wanted) {
+                warn!("{}", e);
+            }
+        }
+    });
+    for property in ["gtk-application-prefer-dark-theme", "gtk-theme-name"].iter() {
+        let follow = follow.clone();
+        connect_settings_notify(property, move || follow());
+    }
+}
+
+// names of the installed theme folders that have a gtk.css, user themes included
+pub fn list_themes(current_working_dir: &Path) -> Vec<String> {
+    let mut themes = Vec::new();
+    for theme_dir in get_theme_dirs(current_working_dir) {
+        if let Ok(entries) = read_dir(theme_dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if path.join("gtk.css").exists() {
+                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                        themes.push(name.to_owned());
                     }
-                } else {
-                    no_css_error();
                 }
-            } else if secondary_config_path.exists() {
-                let new_css = get_gtk_css(&secondary_config_path, &gtk_theme_path);
-                if let Ok(style_provider) = CssProvider::load_from_data(&new_css) {
-                    if let Some(screen) = Screen::get_default() {
-                        StyleContext::add_provider_for_screen(&screen,
-                                                              &style_provider,
-                                                              STYLE_PROVIDER_PRIORITY_APPLICATION as u32);
-                    } else {
-                        no_css_error();
-                    }
-                } else {
-                    no_css_error();
+            }
+        }
+    }
+    themes.sort();
+    themes.dedup();
+    themes
+}
+
+// Light or dark variant of the theme family closest to the desktop's theme.
+// Dark is chosen when gtk-application-prefer-dark-theme is set or when
+// GTK_THEME or the desktop theme name asks for it (e.g. "Adwaita:dark", "Arc-Dark").
+fn resolve_auto_theme(current_working_dir: &Path) -> String {
+    let env_theme = env::var("GTK_THEME").unwrap_or(String::new()).to_lowercase();
+    let desktop_theme = get_settings_string("gtk-theme-name").unwrap_or(String::new()).to_lowercase();
+    // "dark" on its own, arc-darker isn't a dark theme
+    let says_dark = |name: &str| {
+        name.split(|c| c == '-' || c == ':' || c == '_').any(|part| part == "dark")
+    };
+    let dark = get_settings_bool("gtk-application-prefer-dark-theme") || says_dark(&env_theme) ||
+               says_dark(&desktop_theme);
+    // the variant itself, else the longest family it starts with so arc-solid beats arc
+    let matches = |name: &str, &(family, light_variant, dark_variant): &(&str, &str, &str)| {
+        if name == light_variant || name == dark_variant {
+            Some(usize::max_value())
+        } else if name == family || name.starts_with(&format!("{}-", family)) ||
+                  name.starts_with(&format!("{}:", family)) {
+            Some(family.len())
+        } else {
+            None
+        }
+    };
+    let variants = THEME_VARIANTS.iter()
+                                 .filter_map(|variants| {
+                                     maximum(matches(&env_theme, variants),
+                                             matches(&desktop_theme, variants))
+                                         .map(|score| (score, variants))
+                                 })
+                                 .max_by_key(|&(score, _)| score)
+                                 .map(|(_, variants)| variants)
+                                 .unwrap_or(&THEME_VARIANTS[0]);
+    let (wanted, other) = if dark {
+        (variants.2, variants.1)
+    } else {
+        (variants.1, variants.2)
+    };
+    // settle for the other variant if only one of them is installed
+    let installed = list_themes(current_working_dir);
+    if installed.iter().any(|t| t == wanted) {
+        wanted.to_owned()
+    } else if installed.iter().any(|t| t == other) {
+        other.to_owned()
+    } else {
+        DEFAULT_GTK_THEME.to_owned()
+    }
+}
+
+fn replace_provider(slot: &'static LocalKey<RefCell<Option<CssProvider>>>,
+                    provider: Option<CssProvider>,
+                    priority: u32)
+                    -> Result<(), String> {
+    let screen = try!(Screen::get_default().ok_or("No gdk screen found".to_owned()));
+    slot.with(|active| {
+        let mut active = active.borrow_mut();
+        if let Some(ref old_provider) = *active {
+            StyleContext::remove_provider_for_screen(&screen, old_provider);
+        }
+        if let Some(ref new_provider) = provider {
+            StyleContext::add_provider_for_screen(&screen, new_provider, priority);
+        }
+        *active = provider;
+    });
+    Ok(())
+}
+
+fn get_config_path(default_config_path: &Path, secondary_config_path: &Path) -> Result<PathBuf, String> {
+    if default_config_path.exists() {
+        Ok(default_config_path.to_path_buf())
+    } else if secondary_config_path.exists() {
+        Ok(secondary_config_path.to_path_buf())
+    } else {
+        Err("No valid GTK CSS config found".to_owned())
+    }
+}
+
+fn user_css_path() -> Option<PathBuf> {
+    Some(paths::config_dir().join(USER_GTK_CSS))
+}
+
+fn collect_css_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    if let Ok(entries) = read_dir(dir) {
+        let mut paths = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<PathBuf>>();
+        paths.sort();
+        for path in paths {
+            if path.is_dir() {
+                collect_css_files(&path, files);
+            } else if path.extension().map(|e| e == "css").unwrap_or(false) {
+                files.push(path);
+            }
+        }
+    }
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<(PathBuf, Option<SystemTime>)> {
+    files.iter()
+         .map(|path| (path.clone(), metadata(path).and_then(|m| m.modified()).ok()))
+         .collect()
+}
+
+// theme from the config, or else the one named in theme.txt next to the install root
+pub fn read_theme_choice(current_working_dir: &Path) -> String {
+    if let Some(gtk_theme) = config::with(|c| c.theme.clone()) {
+        return gtk_theme;
+    }
+    let mut gtk_theme = String::new();
+    match theme_cfg_path(current_working_dir) {
+        Some(cfg_path) => {
+            match File::open(&cfg_path).and_then(|mut f| f.read_to_string(&mut gtk_theme)) {
+                Ok(_) => {}
+                Err(_) => {
+                    warn!("Could not read theme.txt file to string, defaulting to Arc theme.");
+                    gtk_theme = DEFAULT_GTK_THEME.to_owned();
                 }
-            } else {
-                no_css_error();
             }
         }
-        Err(e) => {
-            println!("{}", e);
+        None => {
+            info!("No theme.txt file found, defaulting to Arc theme.");
+            gtk_theme = DEFAULT_GTK_THEME.to_owned();
         }
     }
+    gtk_theme.trim().to_owned()
+}
+
+// remember the chosen theme in the config
+pub fn save_theme_choice(gtk_theme: &str) -> Result<(), String> {
+    config::update(|c| c.theme = Some(gtk_theme.to_owned()))
+}
+
+fn theme_cfg_path(current_working_dir: &Path) -> Option<PathBuf> {
+    let first_choice = current_working_dir.join("..").join(GTK_THEME_CFG);
+    let second_choice = current_working_dir.join(GTK_THEME_CFG);
+    if first_choice.exists() {
+        Some(first_choice)
+    } else if second_choice.exists() {
+        Some(second_choice)
+    } else {
+        None
+    }
 }
 
 fn get_gtk_css(config_path: &Path, gtk_theme_path: &Path) -> String {
@@ -90,13 +371,13 @@ fn get_gtk_css(config_path: &Path, gtk_theme_path: &Path) -> String {
             match f.read_to_string(&mut gtk_config) {
                 Ok(_) => {}
                 Err(_) => {
-                    println!("Could not read gtk config to string, going with defaults");
+                    warn!("Could not read gtk config to string, going with defaults");
                     gtk_config = "".to_owned();
                 }
             }
         }
         Err(_) => {
-            println!("Could not open gtk config");
+            warn!("Could not open gtk config: {:?}", config_path);
             gtk_config = "".to_owned();
         }
     }
@@ -111,18 +392,34 @@ fn double_slashes(p: &str) -> String {
     p.replace("\\", "\\\\")
 }
 
-fn get_theme_dir(cwd: &Path) -> Result<PathBuf, String> {
-    let first_choice = cwd.join("..").join("themes");
-    let second_choice = cwd.join("themes");
+// user theme dirs first so they can shadow an installed theme of the same name
+fn get_theme_dirs(cwd: &Path) -> Vec<PathBuf> {
+    let mut theme_dirs = Vec::new();
+    for user_dir in [paths::config_dir(), paths::data_dir()].iter() {
+        let user_theme_dir = user_dir.join(THEMES_DIR_NAME);
+        // the same dir in portable mode
+        if !theme_dirs.contains(&user_theme_dir) {
+            theme_dirs.push(user_theme_dir);
+        }
+    }
+    let first_choice = cwd.join("..").join(THEMES_DIR_NAME);
+    let second_choice = cwd.join(THEMES_DIR_NAME);
     if first_choice.exists() {
-        Ok(first_choice)
+        theme_dirs.push(first_choice);
     } else if second_choice.exists() {
-        Ok(second_choice)
-    } else {
-        Err("Failed to get theme dir".to_owned())
+        theme_dirs.push(second_choice);
     }
+    theme_dirs.into_iter().filter(|dir| dir.is_dir()).collect()
+}
+
+fn find_theme(cwd: &Path, gtk_theme: &str) -> Result<PathBuf, String> {
+    get_theme_dirs(cwd)
+        .into_iter()
+        .map(|theme_dir| theme_dir.join(gtk_theme))
+        .find(|dir| dir.join("gtk.css").exists())
+        .ok_or(format!("Theme {} not found in any theme dir", gtk_theme))
 }
 
 fn no_css_error() {
-    println!("No valid GTK CSS config or gdk screen found, using gtk defaults.");
+    warn!("No valid GTK CSS config or gdk screen found, using gtk defaults.");
 }